@@ -1,15 +1,31 @@
-use std::time::Instant;
-
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use kd_tree::KdTree;
 
-use crate::{cell::energy::EnergyMap, settings::DynamicSettings, trackers::BirthTs, *};
+use crate::{
+    cell::energy::EnergyMap,
+    rng::ResetRunEvent,
+    settings::DynamicSettings,
+    trackers::{BirthTs, InstantTracker},
+    *,
+};
 
 pub struct BulletPlugin;
 
 #[derive(Component)]
 pub struct Bullet(pub u32);
 
+/// Every bullet as of the last frame, rebuilt each frame since bullets are fast and short lived
+#[derive(Resource)]
+pub struct BulletTree(pub Option<KdTree<([f32; 2], TreeBullet)>>);
+
+#[derive(Clone, Copy)]
+pub struct TreeBullet {
+    /// Id of the cell that fired it
+    pub owner: u32,
+    pub velocity: Vec2,
+}
+
 #[derive(Bundle)]
 pub struct BulletBundle {
     sprite_bundle: SpriteBundle,
@@ -25,13 +41,27 @@ pub struct BulletBundle {
 
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup)
-            .add_systems(Update, bullet_cleanup);
+        app.insert_resource(BulletTree(None))
+            .add_systems(Startup, setup)
+            .add_systems(Update, bullet_cleanup)
+            .add_systems(Update, reload_bullet_kd_tree)
+            .add_systems(PreUpdate, clear_bullets.run_if(on_event::<ResetRunEvent>()));
     }
 }
 
 fn setup() {}
 
+fn clear_bullets(
+    mut commands: Commands,
+    mut bullet_tree: ResMut<BulletTree>,
+    bullet_query: Query<Entity, With<Bullet>>,
+) {
+    for entity in bullet_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    bullet_tree.0 = None;
+}
+
 fn bullet_cleanup(
     mut commands: Commands,
     mut energy_map: ResMut<EnergyMap>,
@@ -47,13 +77,29 @@ fn bullet_cleanup(
         match energy_map.0.get_mut(&b.0) {
             Some((v, i)) => {
                 *v -= settings.bullet_miss_penalty;
-                *i = Instant::now();
+                *i = InstantTracker::now();
             }
             None => {}
         }
     }
 }
 
+fn reload_bullet_kd_tree(
+    bullet_query: Query<(&Bullet, &Transform, &Velocity), With<Bullet>>,
+    mut bullet_tree: ResMut<BulletTree>,
+) {
+    let mut pts = Vec::new();
+    for (b, t, velocity) in bullet_query.iter() {
+        let item = TreeBullet {
+            owner: b.0,
+            velocity: velocity.linvel,
+        };
+        pts.push(([t.translation.x, t.translation.y], item))
+    }
+
+    bullet_tree.0 = Some(KdTree::build_by_ordered_float(pts));
+}
+
 impl BulletBundle {
     pub fn new(x: f32, y: f32, cell_id: u32, direction: Vec2, asset_server: &AssetServer) -> Self {
         Self {
@@ -0,0 +1,427 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    neat::{Genome, Innovations, NodeKind, GENOME_BIN_MAGIC},
+    nn::{
+        invalid_data, Activation, Crossover, IoLabels, IoMapping, MutationParams, Net, NetError,
+        NetFormat, NetState,
+    },
+    *,
+};
+
+use super::{
+    output_labels,
+    sensor::{input_labels, SensorKind},
+    CellAction, BASE_OUTPUT_LABELS,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BrainKind {
+    /// Fixed `NET_ARCH` dense network
+    Dense,
+    /// NEAT genome that grows its own topology
+    Neat,
+}
+
+#[derive(Component, Clone)]
+pub enum Brain {
+    Dense(Net),
+    Neat(Genome),
+}
+
+/// Recurrent state of a cell's brain, kept between updates
+/// Starts empty when a cell is born, which is the same as an all zero context
+#[derive(Component, Clone, Default)]
+pub struct BrainMemory(pub NetState);
+
+/// Innovation numbers shared by every NEAT genome in the world
+#[derive(Resource, Default)]
+pub struct NeatInnovations(pub Innovations);
+
+/// Layered view of a brain, used to draw it
+/// Nodes are addressed as `(layer, index)`, in the same order `Brain::predict` returns them
+pub struct BrainLayout {
+    pub layer_sizes: Vec<usize>,
+    /// Activation of every node after the input layer
+    pub activations: Vec<Vec<Activation>>,
+    pub edges: Vec<LayoutEdge>,
+}
+
+pub struct LayoutEdge {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub weight: f64,
+}
+
+impl Brain {
+    /// Random brain of the configured `BRAIN_KIND`
+    /// Fails when `NET_ARCH`, `NET_ACTIVATIONS` and `NET_LAYER_KINDS` don't fit together
+    pub fn random(innovations: &mut Innovations, rng: &mut impl Rng) -> Result<Self, NetError> {
+        let brain = match BRAIN_KIND {
+            BrainKind::Dense => Brain::Dense(Net::with_layer_kinds(
+                NET_ARCH.to_vec(),
+                NET_ACTIVATIONS.to_vec(),
+                NET_LAYER_KINDS.to_vec(),
+                rng,
+            )?),
+            BrainKind::Neat => Brain::Neat(Genome::new(
+                NET_ARCH[0],
+                NET_ARCH[NET_ARCH.len() - 1],
+                NET_ACTIVATIONS[0],
+                NET_ACTIVATIONS[NET_ACTIVATIONS.len() - 1],
+                innovations,
+                rng,
+            )),
+        };
+
+        Ok(brain)
+    }
+
+    /// Node values of every layer, the outputs are the last layer
+    pub fn predict(&self, inputs: &[f64]) -> Result<Vec<Vec<f64>>, NetError> {
+        match self {
+            Brain::Dense(net) => net.predict(inputs),
+            Brain::Neat(genome) => genome.predict(inputs),
+        }
+    }
+
+    /// Same as `predict`, recurrent layers read and update `memory`
+    /// NEAT genomes are feed-forward and leave it untouched
+    pub fn predict_with_memory(
+        &self,
+        inputs: &[f64],
+        memory: &mut BrainMemory,
+    ) -> Result<Vec<Vec<f64>>, NetError> {
+        match self {
+            Brain::Dense(net) => net.predict_with_state(inputs, &mut memory.0),
+            Brain::Neat(genome) => genome.predict(inputs),
+        }
+    }
+
+    pub fn output_activation(&self) -> Activation {
+        match self {
+            Brain::Dense(net) => net.output_activation(),
+            Brain::Neat(genome) => genome.output_activation(),
+        }
+    }
+
+    pub fn mutation(&self) -> MutationParams {
+        match self {
+            Brain::Dense(net) => net.mutation(),
+            Brain::Neat(genome) => genome.mutation(),
+        }
+    }
+
+    pub fn mutate(&mut self, innovations: &mut Innovations, rng: &mut impl Rng) {
+        match self {
+            Brain::Dense(net) => net.mutate(rng),
+            Brain::Neat(genome) => genome.mutate(innovations, rng),
+        }
+    }
+
+    /// Brains of different kinds can't be mixed, `self` is cloned instead
+    pub fn crossover(&self, other: &Brain, mode: Crossover, rng: &mut impl Rng) -> Brain {
+        if mode == Crossover::None {
+            return self.clone();
+        }
+
+        match (self, other) {
+            (Brain::Dense(net), Brain::Dense(other_net)) => {
+                Brain::Dense(net.crossover(other_net, mode, rng))
+            }
+            (Brain::Neat(genome), Brain::Neat(other_genome)) => {
+                Brain::Neat(genome.crossover(other_genome, rng))
+            }
+            _ => self.clone(),
+        }
+    }
+
+    /// Brains of different kinds are infinitely far apart
+    pub fn distance(&self, other: &Brain) -> f64 {
+        match (self, other) {
+            (Brain::Dense(net), Brain::Dense(other_net)) => net.distance(other_net),
+            (Brain::Neat(genome), Brain::Neat(other_genome)) => genome.distance(other_genome),
+            _ => f64::INFINITY,
+        }
+    }
+
+    /// Saved with the labels of the current sensors and actions
+    pub fn save(&self, path: &Path, format: NetFormat) -> io::Result<()> {
+        let labels = current_labels();
+        let genome = match self {
+            Brain::Dense(net) => return net.save(path, format, &labels),
+            Brain::Neat(genome) => genome,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let bytes = match format {
+            NetFormat::Json => genome.to_json(&labels)?.into_bytes(),
+            NetFormat::Binary => genome.to_bytes(&labels),
+        };
+        fs::write(path, bytes)
+    }
+
+    /// Loads either kind of brain, lined up by label with the current sensors and actions
+    /// Inputs it never had start unconnected and new outputs at what an idle cell does,
+    /// dense brains still need the hidden layers of `NET_ARCH`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        // The format picks the decoder, so a broken file reports its own decoder's error
+        let (brain, labels) = if bytes.starts_with(GENOME_BIN_MAGIC) {
+            let (genome, labels) = Genome::from_bytes(&bytes)?;
+            (Brain::Neat(genome), labels)
+        } else if is_genome_json(&bytes) {
+            let text = std::str::from_utf8(&bytes).map_err(invalid_data)?;
+            let (genome, labels) = Genome::from_json(text)?;
+            (Brain::Neat(genome), labels)
+        } else {
+            let (net, labels) = Net::decode(&bytes)?;
+            (Brain::Dense(net), labels)
+        };
+
+        let (n_inputs, n_outputs) = brain.io_sizes();
+        let saved = saved_labels(labels, n_inputs, n_outputs)?;
+        let mapping = IoMapping::new(
+            &saved,
+            &current_labels(),
+            CellAction::default().outputs().to_vec(),
+        );
+        let brain = match brain {
+            _ if mapping.is_identity(n_inputs, n_outputs) => brain,
+            Brain::Dense(net) => Brain::Dense(net.remap_io(&mapping)),
+            Brain::Neat(genome) => Brain::Neat(genome.remap_io(&mapping)),
+        };
+        if let Brain::Dense(net) = &brain {
+            net.check_arch(&NET_ARCH)?;
+        }
+
+        Ok(brain)
+    }
+
+    fn io_sizes(&self) -> (usize, usize) {
+        match self {
+            Brain::Dense(net) => {
+                let sizes = net.layer_sizes();
+                (sizes[0], sizes[sizes.len() - 1])
+            }
+            Brain::Neat(genome) => (genome.n_inputs(), genome.n_outputs()),
+        }
+    }
+
+    pub fn layout(&self) -> BrainLayout {
+        match self {
+            Brain::Dense(net) => {
+                let layer_sizes = net.layer_sizes();
+                let activations = net
+                    .activations()
+                    .iter()
+                    .zip(layer_sizes[1..].iter())
+                    .map(|(a, size)| vec![*a; *size])
+                    .collect();
+
+                let mut edges = Vec::new();
+                for layer in 0..layer_sizes.len() - 1 {
+                    for (to, node) in net.layer_weights(layer).iter().enumerate() {
+                        // First weight is the bias, recurrent weights come after the inputs
+                        let inputs = node.iter().skip(1).take(layer_sizes[layer]);
+                        for (from, weight) in inputs.enumerate() {
+                            edges.push(LayoutEdge {
+                                from: (layer, from),
+                                to: (layer + 1, to),
+                                weight: *weight,
+                            });
+                        }
+                    }
+                }
+
+                BrainLayout {
+                    layer_sizes,
+                    activations,
+                    edges,
+                }
+            }
+            Brain::Neat(genome) => {
+                let layers = genome.layers();
+                let mut positions = HashMap::new();
+                for (l, layer) in layers.iter().enumerate() {
+                    for (i, id) in layer.iter().enumerate() {
+                        positions.insert(*id, (l, i));
+                    }
+                }
+
+                let activations = layers[1..]
+                    .iter()
+                    .map(|layer| {
+                        layer
+                            .iter()
+                            .map(|id| {
+                                genome
+                                    .nodes()
+                                    .iter()
+                                    .find(|n| n.id == *id && n.kind != NodeKind::Input)
+                                    .map(|n| n.activation)
+                                    .unwrap_or_default()
+                            })
+                            .collect()
+                    })
+                    .collect();
+                let edges = genome
+                    .connections()
+                    .iter()
+                    .filter(|c| c.enabled)
+                    .map(|c| LayoutEdge {
+                        from: positions[&c.from],
+                        to: positions[&c.to],
+                        weight: c.weight,
+                    })
+                    .collect();
+
+                BrainLayout {
+                    layer_sizes: layers.iter().map(|l| l.len()).collect(),
+                    activations,
+                    edges,
+                }
+            }
+        }
+    }
+}
+
+/// Inputs and outputs of the brains this build makes
+pub fn current_labels() -> IoLabels {
+    IoLabels {
+        inputs: input_labels(),
+        outputs: output_labels(),
+    }
+}
+
+/// Files saved without labels are taken to have the current layout when their sizes match,
+/// otherwise the one brains had before sensors and actions were configurable
+fn saved_labels(labels: IoLabels, n_inputs: usize, n_outputs: usize) -> io::Result<IoLabels> {
+    if labels != IoLabels::default() {
+        return Ok(labels);
+    }
+
+    let original = IoLabels {
+        inputs: [SensorKind::NearestFood, SensorKind::HeadingTurn]
+            .iter()
+            .flat_map(|s| s.labels())
+            .collect(),
+        outputs: BASE_OUTPUT_LABELS.map(String::from).to_vec(),
+    };
+    [current_labels(), original]
+        .into_iter()
+        .find(|l| l.inputs.len() == n_inputs && l.outputs.len() == n_outputs)
+        .ok_or_else(|| {
+            invalid_data(format!(
+                "Brain has {} inputs and {} outputs and no labels to line them up by",
+                n_inputs, n_outputs
+            ))
+        })
+}
+
+/// Saved genomes wrap everything in a `genome` field, dense nets have their layers at the top
+fn is_genome_json(bytes: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(bytes)
+        .is_ok_and(|value| value.get("genome").is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// Unique per run, so parallel or leftover runs don't share the file
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!(
+            "ava-{}-{}-{}.json",
+            name,
+            std::process::id(),
+            nanos
+        ))
+    }
+
+    #[test]
+    fn corrupt_genome_reports_the_genome_error() {
+        let path = temp_path("corrupt-genome");
+        fs::write(&path, r#"{"version": 1, "genome": {"n_inputs": "four"}}"#).unwrap();
+        let err = Brain::load(&path).err().unwrap().to_string();
+        fs::remove_file(&path).unwrap();
+
+        assert!(err.contains("invalid type"), "{}", err);
+    }
+
+    #[test]
+    fn unlabelled_original_brains_load_lined_up() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let net = Net::new(vec![3, NUM_HIDDEN_NODES, 4], &mut rng).unwrap();
+        let path = temp_path("original-brain");
+        fs::write(&path, net.to_json(&IoLabels::default()).unwrap()).unwrap();
+        let brain = Brain::load(&path);
+        fs::remove_file(&path).unwrap();
+        let Ok(Brain::Dense(loaded)) = brain else {
+            panic!("Original brain didn't load");
+        };
+        assert_eq!(loaded.layer_sizes(), NET_ARCH);
+
+        let original = ["dist", "target angle", "cell angle"];
+        let values = [0.3, -0.2, 0.6];
+        let inputs: Vec<f64> = input_labels()
+            .iter()
+            .map(|l| {
+                original
+                    .iter()
+                    .position(|o| o == l)
+                    .map_or(0.9, |i| values[i])
+            })
+            .collect();
+        // Inputs the current sensors don't have are dropped, the same as reading zero
+        let kept: Vec<f64> = original
+            .iter()
+            .zip(values)
+            .map(|(o, v)| {
+                if input_labels().iter().any(|l| l == o) {
+                    v
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let expected = net.predict(&kept).unwrap();
+        let outputs = loaded.predict(&inputs).unwrap();
+        for (i, label) in BASE_OUTPUT_LABELS.iter().enumerate() {
+            let j = output_labels().iter().position(|l| l == label).unwrap();
+            let (expected, found) = (expected.last().unwrap()[i], outputs.last().unwrap()[j]);
+            assert!((expected - found).abs() < 1e-12, "{}", label);
+        }
+    }
+
+    #[test]
+    fn genome_and_net_json_are_told_apart() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let genome = Genome::new(
+            3,
+            2,
+            Activation::Tanh,
+            Activation::Sigmoid,
+            &mut Innovations::default(),
+            &mut rng,
+        );
+        let net = Net::new(vec![3, 2], &mut rng).unwrap();
+
+        let labels = IoLabels::default();
+        assert!(is_genome_json(genome.to_json(&labels).unwrap().as_bytes()));
+        assert!(!is_genome_json(net.to_json(&labels).unwrap().as_bytes()));
+    }
+}
@@ -4,9 +4,9 @@ use bevy_rapier2d::prelude::*;
 use rand::Rng;
 
 use crate::trackers::*;
-use crate::{nn::Net, *};
+use crate::*;
 
-use super::{Brain, Cell};
+use super::{inference::CompiledBrain, Brain, BrainMemory, Cell};
 
 #[derive(Bundle)]
 pub struct CellBundle {
@@ -21,9 +21,12 @@ pub struct CellBundle {
     collider: Collider,
     damping: Damping,
     brain: Brain,
+    brain_memory: BrainMemory,
+    compiled_brain: CompiledBrain,
     num_cells_spawned: NumCellsSpawned,
     fitness_score: FitnessScores,
     external_force: ExternalForce,
+    velocity: Velocity,
     collision_groups: CollisionGroups,
 }
 
@@ -32,11 +35,11 @@ impl CellBundle {
         x: f32,
         y: f32,
         cell_id: u32,
-        net: Net,
+        brain: Brain,
         sprite_path: &str,
         asset_server: &AssetServer,
+        rng: &mut impl Rng,
     ) -> Self {
-        let mut rng = rand::thread_rng();
         let rot = rng.gen_range(0.0..6.0);
         Self {
             sprite_bundle: SpriteBundle {
@@ -56,15 +59,18 @@ impl CellBundle {
             collider: Collider::ball(7.0),
             damping: Damping {
                 angular_damping: 2.0,
-                linear_damping: 2.0,
+                linear_damping: CELL_LINEAR_DAMPING,
             },
-            brain: Brain(net),
+            compiled_brain: CompiledBrain::new(&brain),
+            brain,
+            brain_memory: BrainMemory::default(),
             num_cells_spawned: NumCellsSpawned(0),
             fitness_score: FitnessScores::new(),
             external_force: ExternalForce {
                 force: Vec2::ZERO,
                 torque: 0.0,
             },
+            velocity: Velocity::zero(),
             collision_groups: CollisionGroups {
                 memberships: Group::from_bits_truncate(GRP_CELLS),
                 filters: Group::from_bits_truncate(MASK_CELLS),
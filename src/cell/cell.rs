@@ -1,30 +1,36 @@
-use std::{
-    f32::consts::PI,
-    time::{Duration, Instant},
-};
-
-use bevy::{math::vec2, prelude::*, time::common_conditions::on_timer};
+use bevy::{math::vec2, prelude::*};
 use bevy_rapier2d::prelude::*;
+use kd_tree::KdTree;
 use rand::Rng;
 
 use crate::{
     bullet::BulletBundle,
-    food::FoodTree,
     gui::SimStats,
-    nn::Net,
-    settings::SimSettings,
+    nn::{Crossover, NetError},
+    rng::{ResetRunEvent, SimRng},
+    settings::{DynamicSettings, SimSettings},
     trackers::{
-        BirthPlace, BirthTs, FitnessScores, LastBulletFired, LastUpdated, NumCellsSpawned,
-        OneSecondTimer, PeriodicUpdateInterval,
+        on_sim_timer, BirthPlace, BirthTs, FitnessScores, InstantTracker, LastBulletFired,
+        LastUpdated, NumCellsSpawned, OneSecondTimer, PeriodicUpdateInterval,
     },
     *,
 };
 
 use super::{
+    brain::{Brain, BrainMemory, NeatInnovations},
     bundle::CellBundle,
-    energy::{CellEnergyPlugin, EnergyMap},
+    demo::{seed_from_demo, CellDemoPlugin},
+    energy::{CellEnergyPlugin, EnergyMap, EnergySpentEvent},
     focus::{CellFocusPlugin, FocusedCellNet, FocusedCellStats},
-    user::{UserCellPlugin, UserControlledCell},
+    hall_of_fame::{CellHallOfFamePlugin, HallOfFame},
+    inference::{BrainBatch, CompiledBrain},
+    lineage::{CellDeathEvent, CellLineagePlugin, CellParents, DeathCause},
+    observation::{facing_angle, FoodObservation},
+    seed::{load_brain_seeds, BrainSeeds},
+    sensor::{sense_all, CellSensorPlugin, SensorContext, SensorWorld},
+    species::{CellSpeciesPlugin, SpeciesId, SpeciesMap},
+    trainer::{CellTrainerPlugin, TrainerMode},
+    user::{spawn_user_cell, UserCellPlugin, UserControlledCell},
 };
 
 pub struct CellPlugin;
@@ -33,36 +39,112 @@ pub struct CellPlugin;
 pub struct Cell(pub u32);
 #[derive(Resource)]
 pub struct CellId(pub u32);
-#[derive(Component)]
-pub struct Brain(pub Net);
 
+/// Every cell as of the last refresh, positions lag behind by up to `CELL_TREE_REFRESH_RATE_SECS`
+#[derive(Resource)]
+pub struct CellTree(pub Option<KdTree<([f32; 2], TreeCell)>>);
+
+#[derive(Clone, Copy)]
+pub struct TreeCell {
+    pub entity: Entity,
+    pub id: u32,
+    /// Radians, see `facing_angle`
+    pub heading: f32,
+}
+
+/// Sent when a cell's brain can't be evaluated, the cell gets a new random brain
+#[derive(Event)]
+pub struct BrokenBrainEvent {
+    pub entity: Entity,
+    pub cell_id: u32,
+    pub error: NetError,
+}
+
+/// Outputs every brain has, the ones of `EXTRA_ACTIONS` follow
+pub const BASE_OUTPUT_LABELS: [&str; NUM_BASE_OUTPUT_NODES] =
+    ["spin left", "spin right", "thrust", "shoot"];
+
+#[derive(Default)]
 pub struct CellAction {
-    pub thrust: bool,
-    pub spin_left: bool,
-    pub spin_right: bool,
+    /// Share of `CELL_SPEED`, 0..1
+    pub thrust: f32,
+    /// Radians this update, positive turns left, up to `CELL_TURN_RATE` either way
+    pub turn: f32,
     pub shoot: bool,
+    /// Sideways thrust, -1..1, positive goes left
+    pub strafe: f32,
+    /// 0..1, see `BRAKE_DAMPING`
+    pub brake: f32,
+    /// Backwards thrust, 0..1
+    pub reverse: f32,
+}
+
+/// Actions on top of turning, thrust and shooting, each one in `EXTRA_ACTIONS` gets its own output
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExtraAction {
+    /// The output reads 0.5 for none, higher goes left
+    Strafe,
+    Brake,
+    Reverse,
+}
+
+/// How brain outputs become a `CellAction`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ActionMode {
+    /// Full thrust or none and a fixed turn, past thresholds
+    Discrete,
+    /// Thrust strength and turn rate follow the outputs
+    Continuous,
 }
 
 impl Plugin for CellPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(CellEnergyPlugin)
             .add_plugins(CellFocusPlugin)
+            .add_plugins(CellSpeciesPlugin)
+            .add_plugins(CellTrainerPlugin)
+            .add_plugins(CellDemoPlugin)
+            .add_plugins(CellLineagePlugin)
+            .add_plugins(CellHallOfFamePlugin)
+            .add_plugins(CellSensorPlugin)
             .add_plugins(UserCellPlugin)
             .insert_resource(CellId(0))
-            .add_systems(Startup, setup)
+            .insert_resource(CellTree(None))
+            .insert_resource(BrainSeeds::default())
+            .insert_resource(NeatInnovations::default())
+            // Chained, they all draw from the brain and cell streams
+            .add_systems(
+                Startup,
+                (load_brain_seeds, seed_from_demo, spawn_user_cell, setup).chain(),
+            )
+            .add_event::<BrokenBrainEvent>()
             .add_systems(Update, update_cells_system)
+            .add_systems(Update, replace_broken_brains.after(update_cells_system))
             .add_systems(Update, update_cell_sprite)
             .add_systems(
                 Update,
-                kill_bad_cells.run_if(on_timer(Duration::from_secs_f32(0.5))),
+                reload_cell_kd_tree.run_if(on_sim_timer(CELL_TREE_REFRESH_RATE_SECS)),
+            )
+            .add_systems(Update, kill_bad_cells.run_if(on_sim_timer(0.5)))
+            .add_systems(
+                Update,
+                cell_replication_system
+                    .after(replace_broken_brains)
+                    .run_if(on_sim_timer(0.5)),
             )
             .add_systems(
                 Update,
-                cell_replication_system.run_if(on_timer(Duration::from_secs_f32(0.5))),
+                spawn_cells
+                    .after(cell_replication_system)
+                    .run_if(on_sim_timer(5.0)),
             )
+            .add_systems(PreUpdate, clear_cells.run_if(on_event::<ResetRunEvent>()))
             .add_systems(
                 Update,
-                spawn_cells.run_if(on_timer(Duration::from_secs_f32(5.0))),
+                (load_brain_seeds, seed_from_demo, spawn_user_cell, setup)
+                    .chain()
+                    .before(update_cells_system)
+                    .run_if(on_event::<ResetRunEvent>()),
             );
     }
 }
@@ -71,14 +153,97 @@ fn setup(
     commands: Commands,
     cell_id: ResMut<CellId>,
     asset_server: Res<AssetServer>,
+    brain_seeds: Res<BrainSeeds>,
+    innovations: ResMut<NeatInnovations>,
+    sim_rng: ResMut<SimRng>,
+    settings: Res<DynamicSettings>,
     cell_query: Query<(With<Cell>, Without<UserControlledCell>)>,
 ) {
-    spawn_cells(commands, cell_id, asset_server, cell_query);
+    spawn_cells(
+        commands,
+        cell_id,
+        asset_server,
+        brain_seeds,
+        innovations,
+        sim_rng,
+        settings,
+        cell_query,
+    );
+}
+
+/// Clears the run for `ResetRunEvent`, the same systems as on startup then spawn the new one
+fn clear_cells(
+    mut commands: Commands,
+    mut cell_id: ResMut<CellId>,
+    mut cell_tree: ResMut<CellTree>,
+    mut brain_seeds: ResMut<BrainSeeds>,
+    mut innovations: ResMut<NeatInnovations>,
+    hall_of_fame: Res<HallOfFame>,
+    cell_query: Query<Entity, With<Cell>>,
+) {
+    for entity in cell_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    cell_id.0 = 0;
+    cell_tree.0 = None;
+    *brain_seeds = BrainSeeds::default();
+    *innovations = NeatInnovations::default();
+    hall_of_fame.observe_innovations(&mut innovations.0);
+}
+
+fn reload_cell_kd_tree(
+    cell_query: Query<(&Cell, &Transform, Entity), With<Cell>>,
+    mut cell_tree: ResMut<CellTree>,
+) {
+    let mut pts = Vec::new();
+    for (cell, transform, entity) in cell_query.iter() {
+        let item = TreeCell {
+            entity,
+            id: cell.0,
+            heading: facing_angle(transform),
+        };
+        pts.push(([transform.translation.x, transform.translation.y], item))
+    }
+
+    cell_tree.0 = Some(KdTree::build_by_ordered_float(pts));
+}
+
+fn replace_broken_brains(
+    mut commands: Commands,
+    mut reader: EventReader<BrokenBrainEvent>,
+    mut deaths: EventWriter<CellDeathEvent>,
+    mut innovations: ResMut<NeatInnovations>,
+    mut sim_rng: ResMut<SimRng>,
+) {
+    for event in reader.iter() {
+        let Some(mut entity) = commands.get_entity(event.entity) else {
+            continue;
+        };
+
+        error!("Cell {} has a broken brain: {}", event.cell_id, event.error);
+        match Brain::random(&mut innovations.0, &mut sim_rng.brains) {
+            Ok(brain) => {
+                entity.insert((CompiledBrain::new(&brain), BrainMemory::default(), brain));
+            }
+            Err(err) => {
+                error!(
+                    "Can't build a new brain, removing cell {}: {}",
+                    event.cell_id, err
+                );
+                deaths.send(CellDeathEvent {
+                    cell_id: event.cell_id,
+                    cause: DeathCause::BrokenBrain,
+                });
+                entity.despawn();
+            }
+        }
+    }
 }
 
 fn kill_bad_cells(
     mut commands: Commands,
     mut energy_map: ResMut<EnergyMap>,
+    mut deaths: EventWriter<CellDeathEvent>,
     cell_query: Query<
         (
             &Cell,
@@ -97,6 +262,10 @@ fn kill_bad_cells(
             Some((v, _)) => {
                 if *v <= 0.0 {
                     energy_map.0.remove(&c.0);
+                    deaths.send(CellDeathEvent {
+                        cell_id: c.0,
+                        cause: DeathCause::Starved,
+                    });
                     commands.entity(entity).despawn();
                     continue;
                 }
@@ -112,6 +281,10 @@ fn kill_bad_cells(
                 < 50.0
             {
                 energy_map.0.remove(&c.0);
+                deaths.send(CellDeathEvent {
+                    cell_id: c.0,
+                    cause: DeathCause::Idle,
+                });
                 commands.entity(entity).despawn();
                 continue;
             }
@@ -125,6 +298,10 @@ fn kill_bad_cells(
                 < 25000.0
             {
                 energy_map.0.remove(&c.0);
+                deaths.send(CellDeathEvent {
+                    cell_id: c.0,
+                    cause: DeathCause::Circling,
+                });
                 commands.entity(entity).despawn();
                 continue;
             }
@@ -137,6 +314,10 @@ fn kill_bad_cells(
 
             if x_disp * 3.0 < y_disp || y_disp * 3.0 < x_disp {
                 energy_map.0.remove(&c.0);
+                deaths.send(CellDeathEvent {
+                    cell_id: c.0,
+                    cause: DeathCause::OneDirection,
+                });
                 commands.entity(entity).despawn();
                 continue;
             }
@@ -147,7 +328,7 @@ fn kill_bad_cells(
             match energy_map.0.get_mut(&c.0) {
                 Some((v, i)) => {
                     *v -= NO_BULLET_PENALTY;
-                    *i = Instant::now();
+                    *i = InstantTracker::now();
                 }
                 None => {}
             }
@@ -201,32 +382,47 @@ fn update_cells_system(
     mut commands: Commands,
     one_second_timer: Res<OneSecondTimer>,
     asset_server: Res<AssetServer>,
-    food_tree: Res<FoodTree>,
+    settings: Res<DynamicSettings>,
+    sensor_world: SensorWorld,
     focused_cell_stats: Res<FocusedCellStats>,
     mut focused_cell_net: ResMut<FocusedCellNet>,
+    mut batch: Local<BrainBatch>,
+    mut broken_brains: EventWriter<BrokenBrainEvent>,
+    mut energy_spent: EventWriter<EnergySpentEvent>,
+    brain_query: Query<(&Brain, &CompiledBrain)>,
     mut cell_query: Query<
         (
             &Cell,
             &mut Transform,
-            &Brain,
+            &mut BrainMemory,
             &mut ExternalForce,
+            &mut Damping,
             &mut LastUpdated,
             &mut LastBulletFired,
             &mut FitnessScores,
             &PeriodicUpdateInterval,
+            Entity,
         ),
         (With<Cell>, Without<UserControlledCell>),
     >,
 ) {
+    // Gather the inputs of every cell due for an update
+    batch.clear();
+    let mut inputs = Vec::with_capacity(NUM_INPUT_NODES);
+    // What the fitness rules look at, whatever the sensors are
+    let mut food_observations = Vec::new();
+    let mut focused_memory = None;
     for (
         cell,
-        mut transform,
-        brain,
-        mut external_force,
+        transform,
+        mut brain_memory,
+        _,
+        _,
         mut last_updated,
-        mut last_bullet_fired,
-        mut fitness_scores,
+        last_bullet_fired,
+        _,
         periodic_update_interval,
+        entity,
     ) in cell_query.iter_mut()
     {
         if last_updated.0.elapsed_within(UPDATE_INTERVAL) {
@@ -240,88 +436,83 @@ fn update_cells_system(
         }
 
         last_updated.0.set_instant_now();
-        let mut target_x = 0.0;
-        let mut target_y = 0.0;
-        // Get the closest food
-        let key = (transform.translation.x, transform.translation.y);
-        if let Some(t) = &food_tree.0 {
-            match t.nearest(&[key.0 as f32, key.1 as f32]) {
-                Some(v) => {
-                    let [x, y] = v.item;
-                    target_x = *x;
-                    target_y = *y;
-                }
-                None => {}
-            }
+        let ctx = SensorContext::new(entity, &transform, &last_bullet_fired, &sensor_world);
+        sense_all(&ctx, &mut inputs);
+        food_observations.push(ctx.food_observation());
+        if focused_cell_stats.id == cell.0 {
+            focused_memory = Some((entity, brain_memory.0.clone()));
         }
+        batch.push(entity, &inputs, std::mem::take(&mut brain_memory.0));
+    }
 
-        // NN Inputs
-        let nn_inp_dist = transform
-            .translation
-            .truncate()
-            .distance(vec2(target_x as f32, target_y as f32))
-            / VISION_RADIUS;
-        let nn_inp_dist = if nn_inp_dist > 1.0 { 1.0 } else { nn_inp_dist };
-
-        let nn_inp_angle = angle_between(
-            transform.translation.x,
-            transform.translation.y,
-            target_x,
-            target_y,
-        );
-        let nn_inp_angle = nn_inp_angle / 360.0;
-        let nn_target_angle = if nn_inp_angle < 0.0 {
-            nn_inp_angle + 360.0
-        } else {
-            nn_inp_angle
-        };
+    // Update brains
+    batch.run(&brain_query);
 
-        let nn_cell_angle = (transform.rotation.to_euler(EulerRot::XYZ).2 + PI / 2.0).to_degrees();
-        let nn_cell_angle = if nn_cell_angle < 0.0 {
-            nn_cell_angle + 360.0
-        } else {
-            nn_cell_angle
+    // Act on the outputs
+    for i in 0..batch.len() {
+        let Ok((
+            cell,
+            mut transform,
+            mut brain_memory,
+            mut external_force,
+            mut damping,
+            _,
+            mut last_bullet_fired,
+            mut fitness_scores,
+            _,
+            entity,
+        )) = cell_query.get_mut(batch.entity(i))
+        else {
+            continue;
         };
-        let nn_cell_angle = nn_cell_angle / 360.0;
-        // let angle_diff = nn_cell_angle / 360.0;
-
-        // Update brain
-        let input = [
-            nn_inp_dist as f64,
-            nn_target_angle as f64,
-            nn_cell_angle as f64,
-        ];
-        let output = &brain.0.predict(&input.to_vec());
-        if focused_cell_stats.id == cell.0 {
-            focused_cell_net.0 = output.clone();
+        if let Some(err) = batch.error(i) {
+            broken_brains.send(BrokenBrainEvent {
+                entity,
+                cell_id: cell.0,
+                error: err.clone(),
+            });
+            continue;
         }
+        let Ok((brain, _)) = brain_query.get(entity) else {
+            continue;
+        };
+        brain_memory.0 = batch.take_memory(i);
+        let input = batch.inputs(i);
 
-        let output = &output[NET_ARCH.len() - 1];
-        let mut spin_left = false;
-        let mut spin_right = false;
-        let thrust = output[2] >= 0.7;
-        let shoot = output[3] >= 0.7;
-
-        if output[0] > output[1] {
-            spin_left = true;
-        } else if output[1] > output[0] {
-            spin_right = true;
+        // The batch only keeps the outputs, the panel wants every layer
+        if let Some((focused_entity, memory)) = &focused_memory {
+            if *focused_entity == entity {
+                let mut memory = BrainMemory(memory.clone());
+                focused_cell_net.brain = Some(brain.clone());
+                focused_cell_net.values = brain
+                    .predict_with_memory(input, &mut memory)
+                    .unwrap_or_default();
+                focused_cell_net.memory = brain_memory.0 .0.clone();
+            }
         }
 
-        let fitness = calc_fitness(input, [output[0], output[1], output[2], output[3]]);
-        fitness_scores.push(fitness);
-
-        let action = CellAction {
-            thrust,
-            spin_left,
-            spin_right,
-            shoot,
-        };
+        // Thresholds below assume outputs in 0..1
+        let activation = brain.output_activation();
+        let output: Vec<f64> = batch
+            .outputs(i)
+            .iter()
+            .map(|v| activation.normalize(*v))
+            .collect();
+        let action = CellAction::from_outputs(&output, settings.action_mode);
+        fitness_scores.push(calc_fitness(food_observations[i], &action));
+        let energy_cost = action.energy_cost();
+        if energy_cost > 0.0 {
+            energy_spent.send(EnergySpentEvent {
+                cell_id: cell.0,
+                amount: energy_cost,
+            });
+        }
         perform_cell_action(
             action,
             cell.0,
             &mut last_bullet_fired,
             &mut external_force,
+            &mut damping,
             &mut commands,
             &mut transform,
             &asset_server,
@@ -329,29 +520,154 @@ fn update_cells_system(
     }
 }
 
+impl CellAction {
+    /// Reads normalized brain outputs, see `output_labels`
+    pub fn from_outputs(output: &[f64], mode: ActionMode) -> Self {
+        let shoot = output[3] >= 0.7;
+        let mut action = match mode {
+            ActionMode::Discrete => {
+                let turn = if output[0] > output[1] {
+                    CELL_TURN_RATE
+                } else if output[1] > output[0] {
+                    -CELL_TURN_RATE
+                } else {
+                    0.0
+                };
+                let thrust = if output[2] >= 0.7 { 1.0 } else { 0.0 };
+
+                Self {
+                    thrust,
+                    turn,
+                    shoot,
+                    ..Self::default()
+                }
+            }
+            ActionMode::Continuous => Self {
+                thrust: output[2].clamp(0.0, 1.0) as f32,
+                turn: (output[0] - output[1]).clamp(-1.0, 1.0) as f32 * CELL_TURN_RATE,
+                shoot,
+                ..Self::default()
+            },
+        };
+
+        for (i, extra) in EXTRA_ACTIONS.iter().enumerate() {
+            let value = output[NUM_BASE_OUTPUT_NODES + i];
+            // Same 0.7 threshold as thrust, on both sides for strafing
+            let value = match (extra, mode) {
+                (ExtraAction::Strafe, ActionMode::Discrete) => {
+                    if value >= 0.7 {
+                        1.0
+                    } else if value <= 0.3 {
+                        -1.0
+                    } else {
+                        0.0
+                    }
+                }
+                (ExtraAction::Strafe, ActionMode::Continuous) => {
+                    ((value - 0.5) * 2.0).clamp(-1.0, 1.0) as f32
+                }
+                (_, ActionMode::Discrete) => {
+                    if value >= 0.7 {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+                (_, ActionMode::Continuous) => value.clamp(0.0, 1.0) as f32,
+            };
+            match extra {
+                ExtraAction::Strafe => action.strafe = value,
+                ExtraAction::Brake => action.brake = value,
+                ExtraAction::Reverse => action.reverse = value,
+            }
+        }
+
+        action
+    }
+
+    /// Normalized brain outputs that decode back to this action in either mode
+    pub fn outputs(&self) -> [f64; NUM_OUTPUT_NODES] {
+        let turn = (self.turn / CELL_TURN_RATE).clamp(-1.0, 1.0) as f64;
+        let mut outputs = [0.0; NUM_OUTPUT_NODES];
+        outputs[..NUM_BASE_OUTPUT_NODES].copy_from_slice(&[
+            0.5 + turn / 2.0,
+            0.5 - turn / 2.0,
+            self.thrust as f64,
+            if self.shoot { 1.0 } else { 0.0 },
+        ]);
+        for (i, extra) in EXTRA_ACTIONS.iter().enumerate() {
+            outputs[NUM_BASE_OUTPUT_NODES + i] = match extra {
+                ExtraAction::Strafe => 0.5 + self.strafe as f64 / 2.0,
+                ExtraAction::Brake => self.brake as f64,
+                ExtraAction::Reverse => self.reverse as f64,
+            };
+        }
+
+        outputs
+    }
+
+    /// Energy the extra actions take this update
+    pub fn energy_cost(&self) -> f32 {
+        self.strafe.abs() * STRAFE_ENERGY_COST
+            + self.brake * BRAKE_ENERGY_COST
+            + self.reverse * REVERSE_ENERGY_COST
+    }
+}
+
+impl ExtraAction {
+    pub fn get_label(&self) -> &str {
+        match self {
+            ExtraAction::Strafe => "strafe",
+            ExtraAction::Brake => "brake",
+            ExtraAction::Reverse => "reverse",
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        EXTRA_ACTIONS.contains(self)
+    }
+}
+
+/// What each brain output means, in order, inputs are labelled by their sensors
+pub fn output_labels() -> Vec<String> {
+    BASE_OUTPUT_LABELS
+        .iter()
+        .map(|l| l.to_string())
+        .chain(EXTRA_ACTIONS.iter().map(|a| a.get_label().to_string()))
+        .collect()
+}
+
+impl ActionMode {
+    pub const ALL: [ActionMode; 2] = [ActionMode::Discrete, ActionMode::Continuous];
+
+    pub fn get_label(&self) -> &str {
+        match self {
+            ActionMode::Discrete => "Discrete",
+            ActionMode::Continuous => "Continuous",
+        }
+    }
+}
+
 pub fn perform_cell_action(
     action: CellAction,
     cell_id: u32,
     last_bullet_fired: &mut LastBulletFired,
     external_force: &mut ExternalForce,
+    damping: &mut Damping,
     commands: &mut Commands,
     transform: &mut Transform,
     asset_server: &AssetServer,
 ) {
-    let spin_strength = 0.5;
-
     // Apply Cell force
-    let angle = (transform.rotation.to_euler(EulerRot::XYZ).2 + PI / 2.0) as f64;
-    if action.thrust {
-        external_force.force = vec2(angle.cos() as f32, angle.sin() as f32) * CELL_SPEED;
-    } else {
-        external_force.force = Vec2::ZERO;
-    }
+    let angle = facing_angle(transform);
+    let ahead = vec2(angle.cos(), angle.sin());
+    let force = ahead * (action.thrust - action.reverse * REVERSE_THRUST)
+        + ahead.perp() * action.strafe * STRAFE_THRUST;
+    external_force.force = force * CELL_SPEED;
+    damping.linear_damping = CELL_LINEAR_DAMPING + action.brake * BRAKE_DAMPING;
     // Apply Spin
-    if action.spin_left {
-        transform.rotate_z(spin_strength);
-    } else if action.spin_right {
-        transform.rotate_z(-spin_strength);
+    if action.turn != 0.0 {
+        transform.rotate_z(action.turn);
     }
 
     if !action.shoot {
@@ -362,7 +678,7 @@ pub fn perform_cell_action(
     }
 
     // Bullet spawn
-    let angle = transform.rotation.to_euler(EulerRot::XYZ).2 + PI / 2.0;
+    let angle = facing_angle(transform);
     let x = angle.cos();
     let y = angle.sin();
     let direction = vec2(x, y);
@@ -383,19 +699,59 @@ fn cell_replication_system(
     mut cell_id: ResMut<CellId>,
     energy_map: Res<EnergyMap>,
     stats: Res<SimStats>,
+    settings: Res<DynamicSettings>,
     asset_server: Res<AssetServer>,
-    mut cell_query: Query<(&Cell, &Brain, &mut NumCellsSpawned), With<Cell>>,
+    mut innovations: ResMut<NeatInnovations>,
+    mut sim_rng: ResMut<SimRng>,
+    species_map: Res<SpeciesMap>,
+    mut cell_query: Query<
+        (
+            &Cell,
+            &Brain,
+            &mut NumCellsSpawned,
+            Option<&SpeciesId>,
+            Entity,
+        ),
+        With<Cell>,
+    >,
 ) {
+    if settings.trainer != TrainerMode::Genetic {
+        return;
+    }
+    let sim_rng = &mut *sim_rng;
+    let rng = &mut sim_rng.replication;
     let mut num_cells = cell_query.iter().len();
-    for (c, brain, mut num_cells_spawned) in cell_query.iter_mut() {
-        let mut rng = rand::thread_rng();
+
+    // With fitness sharing, big species don't get more children just for being big
+    let energy_of = |c: &Cell, species_id: Option<&SpeciesId>| {
+        let (v, _) = energy_map.0.get(&c.0)?;
+        match settings.fitness_sharing {
+            true => Some(species_map.shared_energy(*v, species_id)),
+            false => Some(*v),
+        }
+    };
+    let max_energy = match settings.fitness_sharing {
+        true => cell_query
+            .iter()
+            .filter_map(|(c, _, _, species_id, _)| energy_of(c, species_id))
+            .fold(0.0, f32::max),
+        false => stats.max_score,
+    };
+    // No cell has energy to scale against
+    if !max_energy.is_finite() || max_energy <= 0.0 {
+        return;
+    }
+
+    // Pick the parents first, mates are read from the query while building children
+    let mut parents = Vec::new();
+    for (c, _, _, species_id, entity) in cell_query.iter() {
         if num_cells >= NUM_CELLS {
-            continue;
+            break;
         }
 
-        match energy_map.0.get(&c.0) {
-            Some((v, _)) => {
-                if rng.gen_range(0.0..1.0) >= (v / stats.max_score) {
+        match energy_of(c, species_id) {
+            Some(v) => {
+                if rng.gen_range(0.0..1.0) >= (v / max_energy) {
                     continue;
                 }
                 // if rng.gen_range(0.0..100.0) >= (birth_ts.0.elapsed() / stats.max_age) * 20.0 {
@@ -405,88 +761,205 @@ fn cell_replication_system(
                 //     continue;
                 // }
 
-                let x = rng.gen_range(-(W as f32) / 2.0..W as f32 / 2.0);
-                let y = rng.gen_range(-(H as f32) / 2.0..H as f32 / 2.0);
-                let mut child_net = brain.0.clone();
-                child_net.mutate();
-
-                cell_id.0 += 1;
                 num_cells += 1;
-
-                num_cells_spawned.0 += 1;
-                commands.spawn(CellBundle::new(
-                    x,
-                    y,
-                    cell_id.0,
-                    child_net,
-                    CELL_SPRITE,
-                    &asset_server,
-                ));
+                parents.push(entity);
             }
             None => {}
         }
     }
+
+    // Second parents are picked proportional to their energy
+    let mut mates = Vec::new();
+    if settings.crossover != Crossover::None {
+        for (c, _, _, species_id, entity) in cell_query.iter() {
+            if let Some(v) = energy_of(c, species_id) {
+                if v > 0.0 {
+                    mates.push((entity, v));
+                }
+            }
+        }
+    }
+    let total_energy: f32 = mates.iter().map(|(_, v)| v).sum();
+
+    for parent in parents {
+        let Ok((parent_cell, brain, _, species_id, _)) = cell_query.get(parent) else {
+            continue;
+        };
+        let species_id = species_id.copied();
+        let mate = pick_by_energy(&mates, total_energy, parent, rng)
+            .and_then(|e| cell_query.get(e).ok())
+            .map(|(mate_cell, mate_brain, _, _, _)| (mate_cell.0, mate_brain));
+        let parents = CellParents {
+            parent: parent_cell.0,
+            mate: mate.map(|(id, _)| id),
+        };
+
+        let mut child_brain = match mate {
+            Some((_, mate_brain)) => {
+                brain.crossover(mate_brain, settings.crossover, &mut sim_rng.brains)
+            }
+            None => brain.clone(),
+        };
+        child_brain.mutate(&mut innovations.0, &mut sim_rng.brains);
+
+        let x = rng.gen_range(-(W as f32) / 2.0..W as f32 / 2.0);
+        let y = rng.gen_range(-(H as f32) / 2.0..H as f32 / 2.0);
+        cell_id.0 += 1;
+        let mut child = commands.spawn((
+            CellBundle::new(
+                x,
+                y,
+                cell_id.0,
+                child_brain,
+                CELL_SPRITE,
+                &asset_server,
+                &mut sim_rng.cells,
+            ),
+            parents,
+        ));
+        // Children start in their parent's species until the next clustering
+        if let Some(species_id) = species_id {
+            child.insert(species_id);
+        }
+
+        if let Ok((_, _, mut num_cells_spawned, _, _)) = cell_query.get_mut(parent) {
+            num_cells_spawned.0 += 1;
+        }
+    }
+}
+
+/// Roulette wheel selection over `(entity, energy)` pairs, never picks `exclude`
+/// so a parent can't be crossed with itself
+fn pick_by_energy(
+    candidates: &[(Entity, f32)],
+    total_energy: f32,
+    exclude: Entity,
+    rng: &mut impl Rng,
+) -> Option<Entity> {
+    let excluded_energy = candidates
+        .iter()
+        .find(|(e, _)| *e == exclude)
+        .map_or(0.0, |(_, v)| *v);
+    let total_energy = total_energy - excluded_energy;
+    if total_energy <= 0.0 {
+        return None;
+    }
+
+    let mut pick = rng.gen_range(0.0..total_energy);
+    let mut last = None;
+    for (entity, energy) in candidates.iter().filter(|(e, _)| *e != exclude) {
+        if pick < *energy {
+            return Some(*entity);
+        }
+        pick -= energy;
+        last = Some(*entity);
+    }
+
+    last
 }
 
 fn spawn_cells(
     mut commands: Commands,
     mut cell_id: ResMut<CellId>,
     asset_server: Res<AssetServer>,
+    brain_seeds: Res<BrainSeeds>,
+    mut innovations: ResMut<NeatInnovations>,
+    mut sim_rng: ResMut<SimRng>,
+    settings: Res<DynamicSettings>,
     cell_query: Query<(With<Cell>, Without<UserControlledCell>)>,
 ) {
+    // The ES trainer spawns its own candidates
+    if settings.trainer != TrainerMode::Genetic {
+        return;
+    }
     let num_cells = cell_query.iter().len();
     if num_cells > 0 {
         return;
     }
 
-    let mut rng = rand::thread_rng();
-    for _ in 0..NUM_CELLS {
-        let x = rng.gen_range(-(W as f32) / 2.0..W as f32 / 2.0);
-        let y = rng.gen_range(-(H as f32) / 2.0..H as f32 / 2.0);
-        let net = Net::new(NET_ARCH.to_vec());
+    let sim_rng = &mut *sim_rng;
+    for i in 0..NUM_CELLS {
+        let x = sim_rng.cells.gen_range(-(W as f32) / 2.0..W as f32 / 2.0);
+        let y = sim_rng.cells.gen_range(-(H as f32) / 2.0..H as f32 / 2.0);
+        let brain = match brain_seeds.brain(i, &mut innovations.0, &mut sim_rng.brains) {
+            Ok(brain) => brain,
+            Err(err) => {
+                // Every other cell would fail the same way
+                error!("Can't build brains for new cells: {}", err);
+                return;
+            }
+        };
 
         cell_id.0 += 1;
         commands.spawn(CellBundle::new(
             x,
             y,
             cell_id.0,
-            net,
+            brain,
             CELL_SPRITE,
             &asset_server,
+            &mut sim_rng.cells,
+        ));
+    }
+}
+
+/// Spawns `count` cells at random places with `brain`, all but the first get a mutated copy
+pub fn spawn_brain_copies(
+    brain: &Brain,
+    count: usize,
+    commands: &mut Commands,
+    cell_id: &mut CellId,
+    innovations: &mut NeatInnovations,
+    sim_rng: &mut SimRng,
+    asset_server: &AssetServer,
+) {
+    for i in 0..count {
+        let mut child = brain.clone();
+        if i > 0 {
+            child.mutate(&mut innovations.0, &mut sim_rng.brains);
+        }
+
+        let x = sim_rng.cells.gen_range(-(W as f32) / 2.0..W as f32 / 2.0);
+        let y = sim_rng.cells.gen_range(-(H as f32) / 2.0..H as f32 / 2.0);
+        cell_id.0 += 1;
+        commands.spawn(CellBundle::new(
+            x,
+            y,
+            cell_id.0,
+            child,
+            CELL_SPRITE,
+            asset_server,
+            &mut sim_rng.cells,
         ));
     }
 }
 
-fn calc_fitness(inp: [f64; NUM_INPUT_NODES], out: [f64; NUM_OUTPUT_NODES]) -> f32 {
-    // Inp
-    // 1 - dist between cell and target
-    // 2 - angle diff between cell and target
-    let dist = inp[0];
-    let target_angle = inp[1];
-    let cell_angle = inp[2];
-
-    // Out
-    // 0 & 1 - spin direction
-    // 0 > 1 - spin left
-    // 0 < 1 - spin right
-    // 2 - thrust when >= 0.7
-    // 3 - shoot when >= 0.7
-    let spin_l = out[0] > out[1];
-    let spin_r = out[1] > out[0];
-    let thrust = out[2] >= 0.7;
-    let shoot = out[3] >= 0.7;
+fn calc_fitness(food: FoodObservation, action: &CellAction) -> f32 {
+    // Food, see `FoodObservation`
+    // dist between cell and target, in vision radii
+    // bearing of the target, positive on the left
+    let dist = food.distance;
+    let bearing = food.bearing;
+
+    // Action, see `CellAction`
+    // thrust - 0..1, only 0 or 1 in discrete mode
+    // turn - positive turns left, scaled to -1..1 here
+    let thrust = action.thrust;
+    let turn = action.turn / CELL_TURN_RATE;
+    let shoot = action.shoot;
 
     let mut score = 0.0;
     let scale = 1.0;
 
     // Rules
-    if dist > 0.3 && thrust {
-        score += scale;
+    if dist > 0.3 {
+        score += scale * thrust;
     }
-    if cell_angle < target_angle && spin_l {
-        score += scale;
-    } else if cell_angle > target_angle && spin_r {
-        score += scale;
+    // Turning towards the target the short way round
+    if bearing > 0.0 && turn > 0.0 {
+        score += scale * turn;
+    } else if bearing < 0.0 && turn < 0.0 {
+        score += scale * -turn;
     }
     if dist < 0.5 && shoot {
         score += scale;
@@ -495,12 +968,94 @@ fn calc_fitness(inp: [f64; NUM_INPUT_NODES], out: [f64; NUM_OUTPUT_NODES]) -> f3
     (4.0 * scale) - score
 }
 
-fn angle_between(a: f32, b: f32, x: f32, y: f32) -> f32 {
-    let angle_radians = (y - b).atan2(x - a);
-    let mut angle_degrees = angle_radians.to_degrees();
-    if angle_degrees < 0.0 {
-        angle_degrees += 360.0;
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    /// Base outputs followed by neutral extra outputs
+    fn outputs(base: [f64; NUM_BASE_OUTPUT_NODES]) -> Vec<f64> {
+        let mut outputs = base.to_vec();
+        outputs.resize(NUM_OUTPUT_NODES, 0.5);
+        outputs
+    }
+
+    #[test]
+    fn discrete_actions_use_thresholds() {
+        let action = CellAction::from_outputs(&outputs([0.9, 0.1, 0.8, 0.8]), ActionMode::Discrete);
+        assert!(close(action.turn, CELL_TURN_RATE));
+        assert!(close(action.thrust, 1.0));
+        assert!(action.shoot);
+
+        let action = CellAction::from_outputs(&outputs([0.2, 0.6, 0.5, 0.1]), ActionMode::Discrete);
+        assert!(close(action.turn, -CELL_TURN_RATE));
+        assert!(close(action.thrust, 0.0));
+        assert!(!action.shoot);
+    }
+
+    #[test]
+    fn continuous_actions_are_proportional() {
+        let action =
+            CellAction::from_outputs(&outputs([0.75, 0.25, 0.4, 0.0]), ActionMode::Continuous);
+        assert!(close(action.turn, 0.5 * CELL_TURN_RATE));
+        assert!(close(action.thrust, 0.4));
+        assert!(!action.shoot);
+    }
+
+    #[test]
+    fn action_outputs_decode_back() {
+        let action = CellAction {
+            thrust: 1.0,
+            turn: -CELL_TURN_RATE,
+            shoot: true,
+            strafe: -1.0,
+            brake: 1.0,
+            reverse: 1.0,
+        };
+        for mode in ActionMode::ALL {
+            let decoded = CellAction::from_outputs(&action.outputs(), mode);
+            assert!(close(decoded.thrust, action.thrust), "{:?}", mode);
+            assert!(close(decoded.turn, action.turn), "{:?}", mode);
+            assert_eq!(decoded.shoot, action.shoot);
+            for extra in [
+                ExtraAction::Strafe,
+                ExtraAction::Brake,
+                ExtraAction::Reverse,
+            ] {
+                let (found, set) = match extra {
+                    ExtraAction::Strafe => (decoded.strafe, action.strafe),
+                    ExtraAction::Brake => (decoded.brake, action.brake),
+                    ExtraAction::Reverse => (decoded.reverse, action.reverse),
+                };
+                // Actions left out of `EXTRA_ACTIONS` have no output and stay off
+                let expected = if extra.is_enabled() { set } else { 0.0 };
+                assert!(close(found, expected), "{:?} {:?}", extra, mode);
+            }
+        }
+        assert_eq!(output_labels().len(), NUM_OUTPUT_NODES);
     }
 
-    angle_degrees
+    #[test]
+    fn pick_by_energy_never_picks_the_parent() {
+        let parent = Entity::from_raw(0);
+        let other = Entity::from_raw(1);
+        let candidates = [(parent, 100.0), (other, 1.0)];
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            assert_eq!(
+                pick_by_energy(&candidates, 101.0, parent, &mut rng),
+                Some(other)
+            );
+        }
+        assert_eq!(
+            pick_by_energy(&candidates[..1], 100.0, parent, &mut rng),
+            None
+        );
+    }
 }
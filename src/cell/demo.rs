@@ -0,0 +1,265 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    nn::{invalid_data, Net, NetError, NetFormat},
+    rng::SimRng,
+    settings::LaunchArgs,
+    *,
+};
+
+use super::{
+    seed::BrainSeeds, spawn_brain_copies, timestamped_path, Brain, CellId, NeatInnovations,
+};
+
+pub struct CellDemoPlugin;
+
+const DEMO_FILE_VERSION: u32 = 1;
+
+/// What the user cell saw and did at one update
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DemoSample {
+    pub inputs: Vec<f64>,
+    /// Normalized brain outputs that would have taken the same action
+    pub outputs: Vec<f64>,
+}
+
+/// A recording of the user cell, samples in the order they happened
+#[derive(Serialize, Deserialize)]
+pub struct Demo {
+    version: u32,
+    pub samples: Vec<DemoSample>,
+}
+
+/// Records the user cell while it's on, toggled with `R`
+#[derive(Resource, Default)]
+pub struct DemoRecorder {
+    recording: bool,
+    pub demo: Demo,
+    /// Where the last finished recording was saved
+    pub last_saved: Option<PathBuf>,
+}
+
+/// Fits a dense brain to a saved recording and adds copies of it to the population
+#[derive(Event)]
+pub struct CloneDemoEvent(pub PathBuf);
+
+impl Plugin for CellDemoPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DemoRecorder::default())
+            .add_event::<CloneDemoEvent>()
+            .add_systems(Update, handle_keyboard_input)
+            .add_systems(Update, clone_demos);
+    }
+}
+
+fn handle_keyboard_input(keyboard_input: Res<Input<KeyCode>>, mut recorder: ResMut<DemoRecorder>) {
+    if keyboard_input.just_pressed(KeyCode::R) {
+        recorder.toggle();
+    }
+}
+
+/// Brains cloned from the `--demo` recording seed the first population
+pub fn seed_from_demo(
+    args: Res<LaunchArgs>,
+    mut seeds: ResMut<BrainSeeds>,
+    mut sim_rng: ResMut<SimRng>,
+) {
+    let Some(path) = &args.demo else {
+        return;
+    };
+
+    match Demo::load(path)
+        .and_then(|demo| clone_behaviour(&demo, &mut sim_rng.brains).map_err(io::Error::from))
+    {
+        Ok(net) => seeds.0.push(Brain::Dense(net)),
+        Err(err) => error!("Can't clone {}: {}", path.display(), err),
+    }
+}
+
+fn clone_demos(
+    mut commands: Commands,
+    mut reader: EventReader<CloneDemoEvent>,
+    mut seeds: ResMut<BrainSeeds>,
+    mut cell_id: ResMut<CellId>,
+    mut innovations: ResMut<NeatInnovations>,
+    mut sim_rng: ResMut<SimRng>,
+    asset_server: Res<AssetServer>,
+) {
+    let sim_rng = &mut *sim_rng;
+    for e in reader.iter() {
+        let net = match Demo::load(&e.0)
+            .and_then(|demo| clone_behaviour(&demo, &mut sim_rng.brains).map_err(io::Error::from))
+        {
+            Ok(net) => net,
+            Err(err) => {
+                error!("Can't clone {}: {}", e.0.display(), err);
+                continue;
+            }
+        };
+
+        let brain = Brain::Dense(net);
+        let path = cloned_brain_path(&e.0);
+        match brain.save(&path, NetFormat::Json) {
+            Ok(_) => info!("Saved cloned brain to {}", path.display()),
+            Err(err) => error!("Failed to save cloned brain: {}", err),
+        }
+
+        spawn_brain_copies(
+            &brain,
+            BC_INJECT_COUNT,
+            &mut commands,
+            &mut cell_id,
+            &mut innovations,
+            sim_rng,
+            &asset_server,
+        );
+
+        // Repopulations start from it too
+        seeds.0.push(brain);
+    }
+}
+
+/// Fits a new dense brain to a recording with `BC_EPOCHS` passes of gradient descent
+/// Every output is pushed towards what the player did, just inside what its activation can reach
+pub fn clone_behaviour(demo: &Demo, rng: &mut impl Rng) -> Result<Net, NetError> {
+    let mut net = Net::with_layer_kinds(
+        NET_ARCH.to_vec(),
+        NET_ACTIVATIONS.to_vec(),
+        NET_LAYER_KINDS.to_vec(),
+        rng,
+    )?;
+
+    let activation = net.output_activation();
+    let samples: Vec<(Vec<f64>, Vec<f64>)> = demo
+        .samples
+        .iter()
+        .map(|s| {
+            let targets = s
+                .outputs
+                .iter()
+                .map(|v| activation.denormalize(v.clamp(BC_TARGET_MARGIN, 1.0 - BC_TARGET_MARGIN)))
+                .collect();
+            (s.inputs.clone(), targets)
+        })
+        .collect();
+
+    let mut loss = 0.0;
+    for _ in 0..BC_EPOCHS {
+        loss = net.train(&samples, BC_LEARNING_RATE)?;
+    }
+    info!(
+        "Cloned a brain from {} samples, final loss {:.4}",
+        samples.len(),
+        loss
+    );
+
+    Ok(net)
+}
+
+impl DemoRecorder {
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn push(&mut self, inputs: &[f64], outputs: &[f64]) {
+        if self.recording {
+            self.demo.samples.push(DemoSample {
+                inputs: inputs.to_vec(),
+                outputs: outputs.to_vec(),
+            });
+        }
+    }
+
+    /// Starts a new recording, or stops and saves the current one to `DEMOS_DIR`
+    pub fn toggle(&mut self) {
+        if !self.recording {
+            if !IS_USER_ENABLED {
+                warn!("Enable IS_USER_ENABLED to record the user cell");
+                return;
+            }
+            self.demo.samples.clear();
+            self.recording = true;
+            info!("Recording the user cell");
+            return;
+        }
+
+        self.recording = false;
+        if self.demo.samples.is_empty() {
+            warn!("Nothing recorded");
+            return;
+        }
+
+        let path = timestamped_path(DEMOS_DIR, "demo", "json");
+        match self.demo.save(&path) {
+            Ok(_) => {
+                info!(
+                    "Saved {} samples to {}",
+                    self.demo.samples.len(),
+                    path.display()
+                );
+                self.last_saved = Some(path);
+            }
+            Err(err) => error!("Failed to save recording: {}", err),
+        }
+    }
+}
+
+impl Default for Demo {
+    fn default() -> Self {
+        Self {
+            version: DEMO_FILE_VERSION,
+            samples: Vec::new(),
+        }
+    }
+}
+
+impl Demo {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, serde_json::to_string(self).map_err(invalid_data)?)
+    }
+
+    /// Loads a recording and checks every sample against `NET_ARCH`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let demo: Demo = serde_json::from_str(&fs::read_to_string(path)?).map_err(invalid_data)?;
+        if demo.version > DEMO_FILE_VERSION {
+            return Err(invalid_data(format!(
+                "recording version {} is newer than {}",
+                demo.version, DEMO_FILE_VERSION
+            )));
+        }
+        if demo.samples.is_empty() {
+            return Err(invalid_data("recording has no samples"));
+        }
+        for sample in demo.samples.iter() {
+            NetError::check_input_size(NUM_INPUT_NODES, &sample.inputs)?;
+            if sample.outputs.len() != NUM_OUTPUT_NODES {
+                return Err(NetError::OutputSize {
+                    expected: NUM_OUTPUT_NODES,
+                    found: sample.outputs.len(),
+                }
+                .into());
+            }
+        }
+
+        Ok(demo)
+    }
+}
+
+/// `demos/demo-1.json` is saved as `brains/cloned-demo-1.json`
+fn cloned_brain_path(demo_path: &Path) -> PathBuf {
+    let stem = demo_path
+        .file_stem()
+        .map_or("demo".into(), |s| s.to_string_lossy());
+    Path::new(BRAINS_DIR).join(format!("cloned-{}.{}", stem, NetFormat::Json.extension()))
+}
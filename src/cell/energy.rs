@@ -1,24 +1,36 @@
-use std::time::{Duration, Instant};
+use bevy::{prelude::*, utils::HashMap};
 
-use bevy::{prelude::*, time::common_conditions::on_timer, utils::HashMap};
-
-use crate::{settings::DynamicSettings, trackers::FitnessScores, *};
+use crate::{
+    rng::ResetRunEvent,
+    settings::DynamicSettings,
+    trackers::{on_sim_timer, FitnessScores, InstantTracker},
+    *,
+};
 
 use super::{user::UserControlledCell, Cell};
 
 pub struct CellEnergyPlugin;
 
 #[derive(Resource)]
-pub struct EnergyMap(pub HashMap<u32, (f32, Instant)>);
+pub struct EnergyMap(pub HashMap<u32, (f32, InstantTracker)>);
+
+/// Energy a cell used up on its own, like on costly actions
+#[derive(Event)]
+pub struct EnergySpentEvent {
+    pub cell_id: u32,
+    pub amount: f32,
+}
 
 impl Plugin for CellEnergyPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(EnergyMap(HashMap::new())).add_systems(
-            Update,
-            update_cell_energy.run_if(on_timer(Duration::from_secs_f32(
-                ENERGY_UPDATE_INTERVAL_SECS,
-            ))),
-        );
+        app.insert_resource(EnergyMap(HashMap::new()))
+            .add_event::<EnergySpentEvent>()
+            .add_systems(
+                Update,
+                update_cell_energy.run_if(on_sim_timer(ENERGY_UPDATE_INTERVAL_SECS)),
+            )
+            .add_systems(Update, spend_energy)
+            .add_systems(PreUpdate, clear_energy.run_if(on_event::<ResetRunEvent>()));
     }
 }
 
@@ -31,15 +43,28 @@ fn update_cell_energy(
         match energy_map.0.get_mut(&cell.0) {
             Some((v, i)) => {
                 *v -= settings.energy_decay_rate * fitness.get_fitness();
-                *i = Instant::now();
+                *i = InstantTracker::now();
             }
             None => {
-                energy_map.0.insert(cell.0, (BASE_ENERGY, Instant::now()));
+                energy_map
+                    .0
+                    .insert(cell.0, (BASE_ENERGY, InstantTracker::now()));
             }
         }
     }
 
-    energy_map
-        .0
-        .retain(|_, (_, i)| i.elapsed().as_secs_f32() < 10.0);
+    energy_map.0.retain(|_, (_, i)| i.elapsed() < 10.0);
+}
+
+fn spend_energy(mut energy_map: ResMut<EnergyMap>, mut reader: EventReader<EnergySpentEvent>) {
+    for e in reader.iter() {
+        // The timestamp is left alone, spending energy isn't being fed
+        if let Some((v, _)) = energy_map.0.get_mut(&e.cell_id) {
+            *v -= e.amount;
+        }
+    }
+}
+
+fn clear_energy(mut energy_map: ResMut<EnergyMap>) {
+    energy_map.0.clear();
 }
@@ -1,34 +1,50 @@
-use std::time::{Duration, Instant};
-
 use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
 
 use crate::{
-    trackers::{BirthTs, FitnessScores, InstantTracker, NumCellsSpawned},
+    nn::{Net, NetFormat},
+    trackers::{BirthTs, FitnessScores, InstantTracker, LastBulletFired, NumCellsSpawned},
     *,
 };
 
-use super::{cell::Cell, energy::EnergyMap};
+use super::{
+    energy::EnergyMap,
+    sensor::{body_inputs, BodyState, SensorContext},
+    Brain, Cell,
+};
 
 pub struct CellFocusPlugin;
 
 #[derive(Component)]
 pub struct FocusedCell;
 
+/// Brain of the focused cell, the node values of its last prediction and its memory
 #[derive(Resource, Default)]
-pub struct FocusedCellNet(pub Vec<Vec<f64>>);
+pub struct FocusedCellNet {
+    pub brain: Option<Brain>,
+    pub values: Vec<Vec<f64>>,
+    pub memory: Vec<Vec<f64>>,
+}
 
 #[derive(Event)]
 pub struct UnFocusCellEvent(pub u32);
 
+/// Saves the focused cell's brain to `BRAINS_DIR`
+#[derive(Event)]
+pub struct ExportFocusedBrainEvent(pub NetFormat);
+
 #[derive(Resource)]
 pub struct FocusedCellStats {
     pub id: u32,
     pub score: f32,
     pub age: f32,
     pub pos: Vec2,
-    pub last_updated: InstantTracker,
+    /// None until a cell is first focused
+    pub last_updated: Option<InstantTracker>,
     pub num_cells_spawned: u32,
     pub fitness_score: f32,
+    /// Live values of the body sensors, as the brain would get them
+    pub body_inputs: Vec<(String, f64)>,
 }
 
 impl Plugin for CellFocusPlugin {
@@ -36,8 +52,10 @@ impl Plugin for CellFocusPlugin {
         app.insert_resource(FocusedCellStats::new())
             .insert_resource(FocusedCellNet::default())
             .add_event::<UnFocusCellEvent>()
+            .add_event::<ExportFocusedBrainEvent>()
             .add_systems(Update, update_focused_cell_stats)
-            .add_systems(Update, update_focused_cell);
+            .add_systems(Update, update_focused_cell)
+            .add_systems(Update, export_focused_brain);
     }
 }
 
@@ -51,12 +69,21 @@ fn update_focused_cell_stats(
             &Transform,
             &NumCellsSpawned,
             &FitnessScores,
+            &LastBulletFired,
+            Option<&Velocity>,
         ),
         With<FocusedCell>,
     >,
 ) {
-    if let Some((c, birth_ts, transform, num_cells_spawned, fitness_score)) =
-        cells_query.iter().next()
+    if let Some((
+        c,
+        birth_ts,
+        transform,
+        num_cells_spawned,
+        fitness_score,
+        last_bullet_fired,
+        velocity,
+    )) = cells_query.iter().next()
     {
         let id = c.0;
         let score = match energy_map.0.get(&c.0) {
@@ -69,9 +96,15 @@ fn update_focused_cell_stats(
         stats.score = score;
         stats.age = age;
         stats.pos = pos;
-        stats.last_updated.set_instant_now();
+        stats.last_updated = Some(InstantTracker::now());
         stats.num_cells_spawned = num_cells_spawned.0;
         stats.fitness_score = fitness_score.get_fitness();
+        let body = BodyState::new(
+            score,
+            velocity.map_or(Vec2::ZERO, |v| v.linvel),
+            last_bullet_fired,
+        );
+        stats.body_inputs = body_inputs(&SensorContext::body_only(transform, body));
     }
 }
 
@@ -94,6 +127,24 @@ fn update_focused_cell(
     }
 }
 
+fn export_focused_brain(
+    mut reader: EventReader<ExportFocusedBrainEvent>,
+    focused_cell_query: Query<(&Cell, &Brain), With<FocusedCell>>,
+) {
+    for e in reader.iter() {
+        let Some((cell, brain)) = focused_cell_query.iter().next() else {
+            warn!("No cell selected, nothing to export");
+            continue;
+        };
+
+        let path = Net::export_path(cell.0, e.0);
+        match brain.save(&path, e.0) {
+            Ok(_) => info!("Exported brain of cell {} to {}", cell.0, path.display()),
+            Err(err) => error!("Failed to export brain of cell {}: {}", cell.0, err),
+        }
+    }
+}
+
 impl FocusedCellStats {
     fn new() -> Self {
         Self {
@@ -101,17 +152,15 @@ impl FocusedCellStats {
             id: 0,
             pos: Vec2::ZERO,
             score: 0.0,
-            last_updated: InstantTracker(
-                Instant::now()
-                    .checked_sub(Duration::from_secs_f32(10.0))
-                    .unwrap(),
-            ),
+            last_updated: None,
             num_cells_spawned: 0,
             fitness_score: 1.0,
+            body_inputs: Vec::new(),
         }
     }
 
     pub fn is_cell_focused(&self) -> bool {
-        !self.last_updated.elapsed_past(1.0)
+        self.last_updated
+            .is_some_and(|last_updated| last_updated.elapsed_within(1.0))
     }
 }
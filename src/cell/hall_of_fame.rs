@@ -0,0 +1,388 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    neat::Innovations,
+    nn::{invalid_data, NetFormat},
+    rng::{ResetRunEvent, SimRng},
+    trackers::{on_sim_timer, BirthTs, NumCellsSpawned},
+    *,
+};
+
+use super::{
+    energy::EnergyMap, lineage::Lineage, spawn_brain_copies, user::UserControlledCell, Brain, Cell,
+    CellId, NeatInnovations,
+};
+
+pub struct CellHallOfFamePlugin;
+
+const HALL_OF_FAME_FILE_VERSION: u32 = 1;
+const INDEX_FILE: &str = "index.json";
+/// Next to the index, can be passed to `--brains` as is
+const BRAINS_SUBDIR: &str = "brains";
+
+/// What a cell can be remembered for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FameCriterion {
+    PeakEnergy,
+    Lifespan,
+    Offspring,
+}
+
+/// Everything kept about a cell besides its brain
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FameInfo {
+    /// Unique across runs, unlike cell ids
+    pub id: u32,
+    pub cell_id: u32,
+    pub generation: u32,
+    /// Seed of the run the cell lived in
+    pub seed: u64,
+    pub peak_energy: f32,
+    /// Seconds
+    pub lifespan: f32,
+    pub offspring: u32,
+}
+
+pub struct FameEntry {
+    pub info: FameInfo,
+    pub brain: Brain,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HallOfFameFile {
+    version: u32,
+    next_id: u32,
+    entries: Vec<FameInfo>,
+}
+
+/// Best brains ever seen, the top `HALL_OF_FAME_SIZE` by each criterion
+/// Saved to `HALL_OF_FAME_DIR` and loaded back on the next run
+#[derive(Resource)]
+pub struct HallOfFame {
+    entries: Vec<FameEntry>,
+    /// Order the panel lists the entries in
+    pub sort_by: FameCriterion,
+    next_id: u32,
+    /// Entry of every cell of this run that made it in
+    cells: HashMap<u32, u32>,
+    changed: bool,
+}
+
+/// Spawns copies of the brain of a hall of fame entry
+#[derive(Event)]
+pub struct InjectFameEntryEvent(pub u32);
+
+impl Plugin for CellHallOfFamePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(HallOfFame::new())
+            .add_event::<InjectFameEntryEvent>()
+            .add_systems(Startup, load_hall_of_fame)
+            .add_systems(
+                Update,
+                update_hall_of_fame.run_if(on_sim_timer(HALL_OF_FAME_UPDATE_INTERVAL_SECS)),
+            )
+            .add_systems(
+                Update,
+                save_hall_of_fame.run_if(on_sim_timer(HALL_OF_FAME_SAVE_INTERVAL_SECS)),
+            )
+            .add_systems(Update, inject_fame_entries)
+            .add_systems(
+                PreUpdate,
+                forget_run_cells.run_if(on_event::<ResetRunEvent>()),
+            );
+    }
+}
+
+fn load_hall_of_fame(
+    mut hall_of_fame: ResMut<HallOfFame>,
+    mut innovations: ResMut<NeatInnovations>,
+) {
+    let dir = Path::new(HALL_OF_FAME_DIR);
+    if !dir.join(INDEX_FILE).is_file() {
+        return;
+    }
+
+    match HallOfFame::load(dir) {
+        Ok(loaded) => {
+            loaded.observe_innovations(&mut innovations.0);
+            info!("Loaded {} hall of fame brains", loaded.entries.len());
+            *hall_of_fame = loaded;
+        }
+        Err(err) => error!("Failed to load the hall of fame: {}", err),
+    }
+}
+
+fn update_hall_of_fame(
+    mut hall_of_fame: ResMut<HallOfFame>,
+    energy_map: Res<EnergyMap>,
+    lineage: Res<Lineage>,
+    sim_rng: Res<SimRng>,
+    cell_query: Query<
+        (&Cell, &Brain, &BirthTs, &NumCellsSpawned),
+        (With<Cell>, Without<UserControlledCell>),
+    >,
+) {
+    for (cell, brain, birth_ts, num_cells_spawned) in cell_query.iter() {
+        let info = FameInfo {
+            id: 0,
+            cell_id: cell.0,
+            generation: lineage.get(cell.0).map_or(0, |r| r.generation),
+            seed: sim_rng.seed(),
+            peak_energy: energy_map.0.get(&cell.0).map_or(0.0, |(v, _)| *v),
+            lifespan: birth_ts.0.elapsed(),
+            offspring: num_cells_spawned.0,
+        };
+        hall_of_fame.submit(info, brain);
+    }
+    hall_of_fame.prune();
+}
+
+/// Entries stay across resets, the cells of the old run don't
+fn forget_run_cells(mut hall_of_fame: ResMut<HallOfFame>) {
+    hall_of_fame.cells.clear();
+}
+
+fn save_hall_of_fame(mut hall_of_fame: ResMut<HallOfFame>) {
+    if !hall_of_fame.changed {
+        return;
+    }
+
+    match hall_of_fame.save(Path::new(HALL_OF_FAME_DIR)) {
+        Ok(_) => hall_of_fame.changed = false,
+        Err(err) => error!("Failed to save the hall of fame: {}", err),
+    }
+}
+
+fn inject_fame_entries(
+    mut commands: Commands,
+    mut reader: EventReader<InjectFameEntryEvent>,
+    hall_of_fame: Res<HallOfFame>,
+    mut cell_id: ResMut<CellId>,
+    mut innovations: ResMut<NeatInnovations>,
+    mut sim_rng: ResMut<SimRng>,
+    asset_server: Res<AssetServer>,
+) {
+    for e in reader.iter() {
+        let Some(entry) = hall_of_fame.get(e.0) else {
+            warn!("No hall of fame entry {}", e.0);
+            continue;
+        };
+
+        spawn_brain_copies(
+            &entry.brain,
+            HALL_OF_FAME_INJECT_COUNT,
+            &mut commands,
+            &mut cell_id,
+            &mut innovations,
+            &mut sim_rng,
+            &asset_server,
+        );
+        info!(
+            "Injected {} copies of hall of fame entry {}",
+            HALL_OF_FAME_INJECT_COUNT, e.0
+        );
+    }
+}
+
+impl FameCriterion {
+    pub const ALL: [FameCriterion; 3] = [
+        FameCriterion::PeakEnergy,
+        FameCriterion::Lifespan,
+        FameCriterion::Offspring,
+    ];
+
+    pub fn get_label(&self) -> &str {
+        match self {
+            FameCriterion::PeakEnergy => "Peak energy",
+            FameCriterion::Lifespan => "Lifespan",
+            FameCriterion::Offspring => "Offspring",
+        }
+    }
+
+    pub fn value(&self, info: &FameInfo) -> f32 {
+        match self {
+            FameCriterion::PeakEnergy => info.peak_energy,
+            FameCriterion::Lifespan => info.lifespan,
+            FameCriterion::Offspring => info.offspring as f32,
+        }
+    }
+}
+
+impl HallOfFame {
+    fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            sort_by: FameCriterion::PeakEnergy,
+            next_id: 0,
+            cells: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Registers the innovations of the NEAT entries, so new genomes don't reuse their numbers
+    pub fn observe_innovations(&self, innovations: &mut Innovations) {
+        for entry in self.entries.iter() {
+            if let Brain::Neat(genome) = &entry.brain {
+                innovations.observe(genome);
+            }
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&FameEntry> {
+        self.entries.iter().find(|e| e.info.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries, best first by `sort_by`
+    pub fn sorted(&self) -> Vec<&FameEntry> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| {
+            self.sort_by
+                .value(&b.info)
+                .total_cmp(&self.sort_by.value(&a.info))
+        });
+
+        entries
+    }
+
+    /// Updates the entry of a cell already in, or adds it when it beats the current
+    /// last entry of any criterion, the brain is only cloned then
+    fn submit(&mut self, info: FameInfo, brain: &Brain) {
+        if let Some(entry) = self
+            .cells
+            .get(&info.cell_id)
+            .and_then(|id| self.entries.iter_mut().find(|e| e.info.id == *id))
+        {
+            let old = &mut entry.info;
+            if info.peak_energy > old.peak_energy
+                || info.lifespan > old.lifespan
+                || info.offspring > old.offspring
+            {
+                old.peak_energy = old.peak_energy.max(info.peak_energy);
+                old.lifespan = old.lifespan.max(info.lifespan);
+                old.offspring = old.offspring.max(info.offspring);
+                self.changed = true;
+            }
+            return;
+        }
+
+        if !self.qualifies(&info) {
+            return;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.cells.insert(info.cell_id, id);
+        self.entries.push(FameEntry {
+            info: FameInfo { id, ..info },
+            brain: brain.clone(),
+        });
+        self.changed = true;
+    }
+
+    fn qualifies(&self, info: &FameInfo) -> bool {
+        FameCriterion::ALL.iter().any(|criterion| {
+            let value = criterion.value(info);
+            let num_better = self
+                .entries
+                .iter()
+                .filter(|e| criterion.value(&e.info) >= value)
+                .count();
+            num_better < HALL_OF_FAME_SIZE
+        })
+    }
+
+    /// Drops entries that aren't in the top `HALL_OF_FAME_SIZE` of any criterion
+    fn prune(&mut self) {
+        let mut keep = HashSet::new();
+        for criterion in FameCriterion::ALL {
+            let mut ranked: Vec<&FameEntry> = self.entries.iter().collect();
+            ranked.sort_by(|a, b| {
+                criterion
+                    .value(&b.info)
+                    .total_cmp(&criterion.value(&a.info))
+            });
+            keep.extend(ranked.iter().take(HALL_OF_FAME_SIZE).map(|e| e.info.id));
+        }
+
+        let num_entries = self.entries.len();
+        self.entries.retain(|e| keep.contains(&e.info.id));
+        self.cells.retain(|_, id| keep.contains(id));
+        if self.entries.len() != num_entries {
+            self.changed = true;
+        }
+    }
+
+    /// Writes an index of every entry and one brain file per entry,
+    /// brain files of dropped entries are removed
+    fn save(&self, dir: &Path) -> io::Result<()> {
+        let brains_dir = dir.join(BRAINS_SUBDIR);
+        fs::create_dir_all(&brains_dir)?;
+
+        let mut paths = HashSet::new();
+        for entry in self.entries.iter() {
+            let path = Self::brain_path(dir, entry.info.id);
+            if !path.is_file() {
+                entry.brain.save(&path, NetFormat::Json)?;
+            }
+            paths.insert(path);
+        }
+        for dir_entry in fs::read_dir(&brains_dir)? {
+            let path = dir_entry?.path();
+            if path.is_file() && !paths.contains(&path) {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        let file = HallOfFameFile {
+            version: HALL_OF_FAME_FILE_VERSION,
+            next_id: self.next_id,
+            entries: self.entries.iter().map(|e| e.info.clone()).collect(),
+        };
+        let text = serde_json::to_string_pretty(&file).map_err(invalid_data)?;
+        fs::write(dir.join(INDEX_FILE), text)
+    }
+
+    /// Entries whose brain can't be loaded are skipped
+    fn load(dir: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(dir.join(INDEX_FILE))?;
+        let file: HallOfFameFile = serde_json::from_str(&text).map_err(invalid_data)?;
+        if file.version > HALL_OF_FAME_FILE_VERSION {
+            return Err(invalid_data(format!(
+                "hall of fame version {} is newer than {}",
+                file.version, HALL_OF_FAME_FILE_VERSION
+            )));
+        }
+
+        let mut hall_of_fame = Self::new();
+        hall_of_fame.next_id = file.next_id;
+        for info in file.entries {
+            let path = Self::brain_path(dir, info.id);
+            match Brain::load(&path) {
+                Ok(brain) => hall_of_fame.entries.push(FameEntry { info, brain }),
+                Err(err) => warn!("Skipping hall of fame brain {}: {}", path.display(), err),
+            }
+        }
+
+        Ok(hall_of_fame)
+    }
+
+    fn brain_path(dir: &Path, id: u32) -> PathBuf {
+        dir.join(BRAINS_SUBDIR)
+            .join(format!("entry-{}.{}", id, NetFormat::Json.extension()))
+    }
+}
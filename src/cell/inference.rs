@@ -0,0 +1,133 @@
+use bevy::{prelude::*, tasks::ComputeTaskPool};
+
+use crate::{
+    neat::FlatGenome,
+    nn::{FlatNet, NetError, NetState},
+    *,
+};
+
+use super::Brain;
+
+/// Flat copy of a brain, built once when the cell is spawned
+#[derive(Component)]
+pub enum CompiledBrain {
+    Dense(FlatNet),
+    Neat(FlatGenome),
+}
+
+/// Inputs of every cell due for an update, evaluated together in one parallel pass
+/// Kept as a `Local` so the buffers are reused between frames
+#[derive(Default)]
+pub struct BrainBatch {
+    n_inputs: usize,
+    n_outputs: usize,
+    entities: Vec<Entity>,
+    inputs: Vec<f64>,
+    outputs: Vec<f64>,
+    memories: Vec<NetState>,
+    errors: Vec<Option<NetError>>,
+}
+
+impl CompiledBrain {
+    pub fn new(brain: &Brain) -> Self {
+        match brain {
+            Brain::Dense(net) => Self::Dense(FlatNet::new(net)),
+            Brain::Neat(genome) => Self::Neat(FlatGenome::new(genome)),
+        }
+    }
+}
+
+impl BrainBatch {
+    pub fn clear(&mut self) {
+        self.n_inputs = NET_ARCH[0];
+        self.n_outputs = NET_ARCH[NET_ARCH.len() - 1];
+        self.entities.clear();
+        self.inputs.clear();
+        self.outputs.clear();
+        self.memories.clear();
+        self.errors.clear();
+    }
+
+    pub fn push(&mut self, entity: Entity, inputs: &[f64], memory: NetState) {
+        self.entities.push(entity);
+        self.inputs.extend_from_slice(inputs);
+        self.memories.push(memory);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn entity(&self, index: usize) -> Entity {
+        self.entities[index]
+    }
+
+    pub fn inputs(&self, index: usize) -> &[f64] {
+        &self.inputs[index * self.n_inputs..(index + 1) * self.n_inputs]
+    }
+
+    pub fn outputs(&self, index: usize) -> &[f64] {
+        &self.outputs[index * self.n_outputs..(index + 1) * self.n_outputs]
+    }
+
+    /// Why the brain at `index` couldn't be evaluated, its outputs are all zero then
+    pub fn error(&self, index: usize) -> Option<&NetError> {
+        self.errors[index].as_ref()
+    }
+
+    /// Memory after the prediction, to be handed back to the cell
+    pub fn take_memory(&mut self, index: usize) -> NetState {
+        std::mem::take(&mut self.memories[index])
+    }
+
+    /// Evaluates every brain in the batch, split in chunks over the compute task pool
+    pub fn run(&mut self, brain_query: &Query<(&Brain, &CompiledBrain)>) {
+        let (n_inputs, n_outputs) = (self.n_inputs, self.n_outputs);
+        self.outputs.clear();
+        self.outputs.resize(self.entities.len() * n_outputs, 0.0);
+        self.errors.clear();
+        self.errors.resize(self.entities.len(), None);
+        if self.entities.is_empty() {
+            return;
+        }
+
+        let chunk_size = BRAIN_BATCH_CHUNK_SIZE;
+        let chunks = self
+            .entities
+            .chunks(chunk_size)
+            .zip(self.inputs.chunks(chunk_size * n_inputs))
+            .zip(self.outputs.chunks_mut(chunk_size * n_outputs))
+            .zip(self.memories.chunks_mut(chunk_size))
+            .zip(self.errors.chunks_mut(chunk_size));
+
+        ComputeTaskPool::get().scope(|scope| {
+            for ((((entities, inputs), outputs), memories), errors) in chunks {
+                scope.spawn(async move {
+                    let mut scratch = Vec::new();
+                    for (i, entity) in entities.iter().enumerate() {
+                        let Ok((_, compiled)) = brain_query.get(*entity) else {
+                            errors[i] = Some(NetError::MissingBrain);
+                            continue;
+                        };
+                        let inputs = &inputs[i * n_inputs..(i + 1) * n_inputs];
+                        let outputs = &mut outputs[i * n_outputs..(i + 1) * n_outputs];
+
+                        let result = match compiled {
+                            CompiledBrain::Dense(flat) => {
+                                flat.predict_into(inputs, &mut memories[i], &mut scratch, outputs)
+                            }
+                            CompiledBrain::Neat(flat) => {
+                                flat.predict_into(inputs, &mut scratch, outputs)
+                            }
+                        };
+                        errors[i] = result.err();
+                    }
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{nn::invalid_data, rng::ResetRunEvent, trackers::sim_seconds, *};
+
+use super::{timestamped_path, user::UserControlledCell, Cell};
+
+pub struct CellLineagePlugin;
+
+const LINEAGE_FILE_VERSION: u32 = 1;
+
+/// Cells a child was bred from, read once when its lineage record is made
+#[derive(Component, Clone, Copy)]
+pub struct CellParents {
+    pub parent: u32,
+    /// Second parent when the child came from crossover
+    pub mate: Option<u32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum DeathCause {
+    Starved,
+    /// Stayed close to where it was born
+    Idle,
+    /// Kept circling around where it was born
+    Circling,
+    /// Only ever moved along one axis
+    OneDirection,
+    BrokenBrain,
+    /// Removed by the ES trainer
+    Trainer,
+}
+
+/// Sent right before a cell is despawned
+#[derive(Event)]
+pub struct CellDeathEvent {
+    pub cell_id: u32,
+    pub cause: DeathCause,
+}
+
+/// Saves every lineage record to `LINEAGE_DIR`
+#[derive(Event)]
+pub struct ExportLineageEvent(pub LineageFormat);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineageFormat {
+    Json,
+    /// Graphviz, parents point to their children
+    Dot,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LineageRecord {
+    pub id: u32,
+    pub parent: Option<u32>,
+    pub mate: Option<u32>,
+    /// Steps from the first cell without a parent
+    pub generation: u32,
+    /// Seconds since the simulation started
+    pub born_at: f32,
+    pub died_at: Option<f32>,
+    pub death_cause: Option<DeathCause>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LineageFile {
+    version: u32,
+    records: Vec<LineageRecord>,
+}
+
+/// Cells of the run by id, records are kept after the cell is despawned
+/// Past `LINEAGE_MAX_RECORDS` dead cells are forgotten, those without living descendants first
+#[derive(Resource, Default)]
+pub struct Lineage {
+    records: BTreeMap<u32, LineageRecord>,
+}
+
+impl Plugin for CellLineagePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Lineage::default())
+            .add_event::<CellDeathEvent>()
+            .add_event::<ExportLineageEvent>()
+            .add_systems(Update, record_births)
+            .add_systems(Update, record_deaths.after(record_births))
+            .add_systems(Update, export_lineage)
+            .add_systems(PreUpdate, clear_lineage.run_if(on_event::<ResetRunEvent>()));
+    }
+}
+
+fn record_births(
+    mut lineage: ResMut<Lineage>,
+    cell_query: Query<(&Cell, Option<&CellParents>), (Added<Cell>, Without<UserControlledCell>)>,
+) {
+    for (cell, parents) in cell_query.iter() {
+        lineage.record_birth(cell.0, parents.copied(), sim_seconds());
+    }
+    lineage.prune(LINEAGE_MAX_RECORDS);
+}
+
+fn record_deaths(mut lineage: ResMut<Lineage>, mut reader: EventReader<CellDeathEvent>) {
+    for e in reader.iter() {
+        lineage.record_death(e.cell_id, e.cause, sim_seconds());
+    }
+}
+
+/// Ids start over with the new run
+fn clear_lineage(mut lineage: ResMut<Lineage>) {
+    *lineage = Lineage::default();
+}
+
+fn export_lineage(lineage: Res<Lineage>, mut reader: EventReader<ExportLineageEvent>) {
+    for e in reader.iter() {
+        let path = e.0.export_path();
+        match lineage.save(&path, e.0) {
+            Ok(_) => info!(
+                "Exported {} lineage records to {}",
+                lineage.len(),
+                path.display()
+            ),
+            Err(err) => error!("Failed to export lineage: {}", err),
+        }
+    }
+}
+
+impl Lineage {
+    pub fn get(&self, id: u32) -> Option<&LineageRecord> {
+        self.records.get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn max_generation(&self) -> u32 {
+        self.records
+            .values()
+            .map(|r| r.generation)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Parent, grandparent and so on, closest first
+    pub fn ancestors(&self, id: u32) -> Vec<u32> {
+        let mut ancestors = Vec::new();
+        let mut current = self.get(id).and_then(|r| r.parent);
+        while let Some(parent) = current {
+            ancestors.push(parent);
+            current = self.get(parent).and_then(|r| r.parent);
+        }
+
+        ancestors
+    }
+
+    /// Oldest ancestor through the first parents, the cell itself when it has none
+    pub fn founder(&self, id: u32) -> u32 {
+        self.ancestors(id).last().copied().unwrap_or(id)
+    }
+
+    fn record_birth(&mut self, id: u32, parents: Option<CellParents>, now: f32) {
+        let generation = parents
+            .and_then(|p| self.get(p.parent))
+            .map_or(0, |r| r.generation + 1);
+        self.records.insert(
+            id,
+            LineageRecord {
+                id,
+                parent: parents.map(|p| p.parent),
+                mate: parents.and_then(|p| p.mate),
+                generation,
+                born_at: now,
+                died_at: None,
+                death_cause: None,
+            },
+        );
+    }
+
+    fn record_death(&mut self, id: u32, cause: DeathCause, now: f32) {
+        let Some(record) = self.records.get_mut(&id) else {
+            return;
+        };
+
+        // A cell can be killed twice in the same frame, the first cause sticks
+        if record.died_at.is_none() {
+            record.died_at = Some(now);
+            record.death_cause = Some(cause);
+        }
+    }
+
+    /// Drops dead cells once there are more than `max` records, down to three quarters of it
+    /// so it doesn't run on every birth. Branches that died out go first, oldest first,
+    /// then the oldest ancestors, whose children are left with a parent that has no record
+    fn prune(&mut self, max: usize) {
+        if self.records.len() <= max {
+            return;
+        }
+        let target = max - max / 4;
+
+        let mut num_children: HashMap<u32, usize> = HashMap::new();
+        for record in self.records.values() {
+            for parent in record.parent.iter().chain(record.mate.iter()) {
+                *num_children.entry(*parent).or_default() += 1;
+            }
+        }
+        let is_dead_leaf = |record: &LineageRecord, num_children: &HashMap<u32, usize>| {
+            record.died_at.is_some() && num_children.get(&record.id).is_none_or(|n| *n == 0)
+        };
+
+        let mut leaves: BTreeSet<u32> = self
+            .records
+            .values()
+            .filter(|r| is_dead_leaf(r, &num_children))
+            .map(|r| r.id)
+            .collect();
+        while self.records.len() > target {
+            let Some(id) = leaves.pop_first() else {
+                break;
+            };
+            let Some(record) = self.records.remove(&id) else {
+                continue;
+            };
+            for parent in record.parent.iter().chain(record.mate.iter()) {
+                if let Some(n) = num_children.get_mut(parent) {
+                    *n -= 1;
+                }
+                if let Some(parent) = self.records.get(parent) {
+                    if is_dead_leaf(parent, &num_children) {
+                        leaves.insert(parent.id);
+                    }
+                }
+            }
+        }
+
+        let dead: Vec<u32> = self
+            .records
+            .values()
+            .filter(|r| r.died_at.is_some())
+            .map(|r| r.id)
+            .collect();
+        for id in dead {
+            if self.records.len() <= target {
+                break;
+            }
+            self.records.remove(&id);
+        }
+    }
+
+    pub fn save(&self, path: &Path, format: LineageFormat) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let text = match format {
+            LineageFormat::Json => self.to_json()?,
+            LineageFormat::Dot => self.to_dot(),
+        };
+        fs::write(path, text)
+    }
+
+    pub fn to_json(&self) -> io::Result<String> {
+        let file = LineageFile {
+            version: LINEAGE_FILE_VERSION,
+            records: self.records.values().cloned().collect(),
+        };
+        serde_json::to_string_pretty(&file).map_err(invalid_data)
+    }
+
+    /// One node per cell, solid edges from the parent and dashed ones from the mate
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph lineage {\n    node [shape=box];\n");
+        for record in self.records.values() {
+            let fate = match (record.died_at, record.death_cause) {
+                (Some(t), Some(cause)) => format!("{:?} at {:.0}s", cause, t),
+                _ => "alive".to_string(),
+            };
+            let _ = writeln!(
+                dot,
+                "    {} [label=\"#{}\\ngen {}\\nborn {:.0}s\\n{}\"];",
+                record.id, record.id, record.generation, record.born_at, fate
+            );
+            if let Some(parent) = record.parent {
+                let _ = writeln!(dot, "    {} -> {};", parent, record.id);
+            }
+            if let Some(mate) = record.mate {
+                let _ = writeln!(dot, "    {} -> {} [style=dashed];", mate, record.id);
+            }
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+impl LineageFormat {
+    pub fn extension(&self) -> &str {
+        match self {
+            LineageFormat::Json => "json",
+            LineageFormat::Dot => "dot",
+        }
+    }
+
+    pub fn export_path(&self) -> PathBuf {
+        timestamped_path(LINEAGE_DIR, "lineage", self.extension())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parents(parent: u32) -> Option<CellParents> {
+        Some(CellParents { parent, mate: None })
+    }
+
+    #[test]
+    fn prune_drops_extinct_branches_before_ancestors() {
+        let mut lineage = Lineage::default();
+        // 0 -> 1 -> 2 is alive at the end, 0 -> 3 -> 4 died out
+        lineage.record_birth(0, None, 0.0);
+        lineage.record_birth(1, parents(0), 1.0);
+        lineage.record_birth(3, parents(0), 1.0);
+        lineage.record_birth(2, parents(1), 2.0);
+        lineage.record_birth(4, parents(3), 2.0);
+        for id in [0, 1, 3, 4] {
+            lineage.record_death(id, DeathCause::Starved, 3.0);
+        }
+
+        lineage.prune(4);
+        assert_eq!(lineage.len(), 3);
+        assert_eq!(lineage.ancestors(2), vec![1, 0]);
+        assert!(lineage.get(3).is_none() && lineage.get(4).is_none());
+
+        lineage.prune(2);
+        assert_eq!(lineage.len(), 2);
+        assert!(lineage.get(0).is_none());
+        // The child still names its parent, the chain just stops there
+        assert_eq!(lineage.ancestors(2), vec![1, 0]);
+    }
+}
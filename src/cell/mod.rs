@@ -1,7 +1,31 @@
+mod brain;
 mod bundle;
 mod cell;
+pub mod demo;
 pub mod energy;
 pub mod focus;
+pub mod hall_of_fame;
+pub mod inference;
+pub mod lineage;
+pub mod observation;
+pub mod seed;
+pub mod sensor;
+pub mod species;
+pub mod trainer;
 pub mod user;
 
+pub use brain::*;
 pub use cell::*;
+
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// `dir/prefix-<unix seconds>.extension`, for files saved from the gui
+pub fn timestamped_path(dir: &str, prefix: &str, extension: &str) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Path::new(dir).join(format!("{}-{}.{}", prefix, secs, extension))
+}
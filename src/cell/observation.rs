@@ -0,0 +1,101 @@
+use std::f32::consts::{PI, TAU};
+
+use bevy::prelude::*;
+
+use crate::*;
+
+/// Where the nearest food is, as the fitness rules see it
+#[derive(Clone, Copy, Debug)]
+pub struct FoodObservation {
+    /// In vision radii, capped at 1
+    pub distance: f64,
+    /// Radians, see `relative_bearing`
+    pub bearing: f32,
+}
+
+impl FoodObservation {
+    pub fn new(transform: &Transform, food: Vec2) -> Self {
+        let pos = transform.translation.truncate();
+        Self {
+            distance: (pos.distance(food) / VISION_RADIUS).min(1.0) as f64,
+            bearing: relative_bearing(transform, food),
+        }
+    }
+}
+
+/// Direction a cell faces in radians, the sprite points up when the rotation is zero
+pub fn facing_angle(transform: &Transform) -> f32 {
+    transform.rotation.to_euler(EulerRot::XYZ).2 + PI / 2.0
+}
+
+/// Angle from where the cell faces to `target` in -PI..PI, positive when the target is on its left
+pub fn relative_bearing(transform: &Transform, target: Vec2) -> f32 {
+    let offset = target - transform.translation.truncate();
+    wrap_angle(offset.y.atan2(offset.x) - facing_angle(transform))
+}
+
+/// Same angle in -PI..PI
+pub fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+/// Absolute angle as a fraction of a full turn in 0..1, how brains saw angles before `encode_angle`
+pub fn encode_turn(angle: f32) -> f64 {
+    (angle.rem_euclid(TAU) / TAU) as f64
+}
+
+/// Sine and cosine shifted into 0..1 like every other input, so there's no seam at a full turn
+/// Straight ahead is (0.5, 1), nothing to point at is (0.5, 0.5)
+pub fn encode_angle(angle: f32) -> [f64; 2] {
+    [
+        (angle.sin() as f64 + 1.0) / 2.0,
+        (angle.cos() as f64 + 1.0) / 2.0,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn encode_angle_has_no_seam() {
+        let [sin, cos] = encode_angle(0.0);
+        assert!(close(sin, 0.5) && close(cos, 1.0));
+
+        let [left_sin, left_cos] = encode_angle(PI - 1e-4);
+        let [right_sin, right_cos] = encode_angle(-PI + 1e-4);
+        assert!((left_sin - right_sin).abs() < 1e-3 && close(left_cos, right_cos));
+        assert!(close(left_cos, 0.0));
+    }
+
+    #[test]
+    fn bearing_is_relative_to_the_heading() {
+        // No rotation faces up
+        let transform = Transform::default();
+        assert!(close(facing_angle(&transform) as f64, (PI / 2.0) as f64));
+        assert!(close(relative_bearing(&transform, Vec2::Y) as f64, 0.0));
+        assert!(close(
+            relative_bearing(&transform, Vec2::NEG_X) as f64,
+            (PI / 2.0) as f64
+        ));
+        assert!(close(
+            relative_bearing(&transform, Vec2::X) as f64,
+            (-PI / 2.0) as f64
+        ));
+
+        let turned = Transform::from_rotation(Quat::from_rotation_z(PI / 2.0));
+        assert!(close(relative_bearing(&turned, Vec2::NEG_X) as f64, 0.0));
+    }
+
+    #[test]
+    fn wrap_and_turn_stay_in_range() {
+        assert!(close(wrap_angle(3.0 * PI / 2.0) as f64, (-PI / 2.0) as f64));
+        assert!(close(wrap_angle(-3.0 * PI / 2.0) as f64, (PI / 2.0) as f64));
+        assert!(close(encode_turn(-PI / 2.0), 0.75));
+        assert!(close(encode_turn(PI / 2.0), 0.25));
+    }
+}
@@ -0,0 +1,89 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{neat::Innovations, nn::NetError, settings::LaunchArgs};
+
+use super::{Brain, NeatInnovations};
+
+/// Brains loaded from disk that new populations are seeded from
+#[derive(Resource, Default)]
+pub struct BrainSeeds(pub Vec<Brain>);
+
+pub fn load_brain_seeds(
+    args: Res<LaunchArgs>,
+    mut seeds: ResMut<BrainSeeds>,
+    mut innovations: ResMut<NeatInnovations>,
+) {
+    let Some(path) = &args.brains else {
+        return;
+    };
+
+    seeds.0 = read_brains(path);
+    for brain in seeds.0.iter() {
+        if let Brain::Neat(genome) = brain {
+            innovations.0.observe(genome);
+        }
+    }
+    if seeds.0.is_empty() {
+        warn!(
+            "No brains loaded from {}, using random brains",
+            path.display()
+        );
+    } else {
+        info!("Loaded {} brains from {}", seeds.0.len(), path.display());
+    }
+}
+
+impl BrainSeeds {
+    /// Brain for the `index`th cell of a new population
+    /// The saved brains are used as is, mutated copies of them fill the remaining slots
+    pub fn brain(
+        &self,
+        index: usize,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) -> Result<Brain, NetError> {
+        if self.0.is_empty() {
+            return Brain::random(innovations, rng);
+        }
+
+        let mut brain = self.0[index % self.0.len()].clone();
+        if index >= self.0.len() {
+            brain.mutate(innovations, rng);
+        }
+
+        Ok(brain)
+    }
+}
+
+fn read_brains(path: &Path) -> Vec<Brain> {
+    if !path.is_dir() {
+        return read_brain(path).into_iter().collect();
+    }
+
+    let mut paths = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file())
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            error!("Failed to read {}: {}", path.display(), err);
+            return Vec::new();
+        }
+    };
+    paths.sort();
+
+    paths.iter().filter_map(|p| read_brain(p)).collect()
+}
+
+fn read_brain(path: &Path) -> Option<Brain> {
+    match Brain::load(path) {
+        Ok(net) => Some(net),
+        Err(err) => {
+            warn!("Skipping brain {}: {}", path.display(), err);
+            None
+        }
+    }
+}
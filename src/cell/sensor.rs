@@ -0,0 +1,849 @@
+use bevy::{ecs::system::SystemParam, math::vec2, prelude::*};
+use bevy_prototype_debug_lines::DebugLines;
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    bullet::{Bullet, BulletTree},
+    food::{Food, FoodTree},
+    trackers::LastBulletFired,
+    *,
+};
+
+use super::{
+    energy::EnergyMap,
+    focus::FocusedCell,
+    observation::{
+        encode_angle, encode_turn, facing_angle, relative_bearing, wrap_angle, FoodObservation,
+    },
+    Cell, CellTree,
+};
+
+pub struct CellSensorPlugin;
+
+/// Everything a sensor can look at when a cell is updated
+pub struct SensorContext<'a> {
+    pub transform: &'a Transform,
+    pub body: BodyState,
+    /// Closest food at any distance, the origin when there's none
+    /// Only the fitness rules look this far, sensors stay within `VISION_RADIUS`
+    pub nearest_food: Vec2,
+    /// Up to `FOOD_SENSOR_COUNT` food within `VISION_RADIUS`, closest first
+    /// Only looked up when `SENSORS` has `Food`
+    pub visible_food: Vec<Vec2>,
+    /// One hit per vision ray, only cast when `SENSORS` has `Rays`
+    pub rays: Vec<RayHit>,
+    /// Closest other cell at any distance, only looked up when `SENSORS` has `NearestCell`
+    pub nearest_cell: Option<NearbyCell>,
+    /// Closest bullet in vision that's getting closer, only looked up when `SENSORS` has `Bullets`
+    pub incoming_bullet: Option<IncomingBullet>,
+}
+
+/// A bullet as seen from the cell being updated, the cell is taken as standing still
+#[derive(Clone, Copy, Debug)]
+pub struct IncomingBullet {
+    pub pos: Vec2,
+    /// Seconds until the bullet is the closest it will get
+    pub time_to_closest: f32,
+    /// Fired by the cell itself
+    pub is_own: bool,
+}
+
+/// What a cell can tell about itself
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BodyState {
+    pub energy: f32,
+    pub velocity: Vec2,
+    /// Seconds until it can fire again
+    pub cooldown: f32,
+}
+
+/// Another cell as seen from the cell being updated
+#[derive(Clone, Copy, Debug)]
+pub struct NearbyCell {
+    pub pos: Vec2,
+    /// Radians, see `facing_angle`
+    pub heading: f32,
+    pub energy: f32,
+}
+
+/// The world around the cells, as the sensors see it
+#[derive(SystemParam)]
+pub struct SensorWorld<'w, 's> {
+    food_tree: Res<'w, FoodTree>,
+    cell_tree: Res<'w, CellTree>,
+    bullet_tree: Res<'w, BulletTree>,
+    energy_map: Res<'w, EnergyMap>,
+    rapier_context: Res<'w, RapierContext>,
+    velocity_query: Query<'w, 's, &'static Velocity>,
+    food_query: Query<'w, 's, (), With<Food>>,
+    cell_query: Query<'w, 's, &'static Cell>,
+    bullet_query: Query<'w, 's, (), With<Bullet>>,
+}
+
+/// What a vision ray can stop at
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HitKind {
+    Food,
+    Cell,
+    Bullet,
+    /// Edge of the world
+    Wall,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub direction: Vec2,
+    /// Nothing within `VISION_RADIUS` when `None`
+    pub kind: Option<HitKind>,
+    /// `VISION_RADIUS` when nothing was hit
+    pub distance: f32,
+}
+
+/// A source of brain inputs, every value it writes is in 0..1
+/// Sensors are listed in `SENSORS`, which also sets the size of the input layer
+pub trait Sensor {
+    const NUM_INPUTS: usize;
+
+    /// One label per input, in order
+    fn labels(&self) -> Vec<String>;
+
+    /// Appends exactly `NUM_INPUTS` values
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>);
+}
+
+/// Every sensor a cell can have
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SensorKind {
+    /// Closest food at any distance, see `NearestFoodSensor`
+    NearestFood,
+    /// Heading as a fraction of a turn, see `HeadingTurnSensor`
+    HeadingTurn,
+    /// Closest food in vision, see `FoodSensor`
+    Food,
+    Heading,
+    /// Vision rays, see `RaySensor`
+    Rays,
+    NearestCell,
+    /// Closest incoming bullet, see `BulletSensor`
+    Bullets,
+    Energy,
+    Velocity,
+    Cooldown,
+}
+
+/// Distance and absolute direction of the closest food at any distance
+/// Together with `HeadingTurnSensor`, the inputs brains had before sensors were pluggable
+pub struct NearestFoodSensor;
+
+/// Absolute direction the cell faces, a full turn maps to 0..1
+pub struct HeadingTurnSensor;
+
+/// Presence, distance and bearing of the `FOOD_SENSOR_COUNT` closest food in vision
+pub struct FoodSensor;
+
+/// Direction the cell is facing
+pub struct HeadingSensor;
+
+/// Distance, bearing, heading and energy of the closest other cell
+pub struct NearestCellSensor;
+
+/// Time to closest approach, bearing and owner of the closest incoming bullet
+pub struct BulletSensor;
+
+/// Own energy out of `MAX_ENERGY`
+pub struct EnergySensor;
+
+/// Own velocity along and across the heading
+pub struct VelocitySensor;
+
+/// Time left before the cell can fire again
+pub struct CooldownSensor;
+
+/// Fan of `VISION_NUM_RAYS` rays, each one tells what it hit first and how close
+pub struct RaySensor;
+
+impl Plugin for CellSensorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, draw_focused_cell_rays);
+    }
+}
+
+fn draw_focused_cell_rays(
+    world: SensorWorld,
+    mut lines: ResMut<DebugLines>,
+    focused_cell_query: Query<(&Transform, Entity), With<FocusedCell>>,
+) {
+    if !SENSORS.contains(&SensorKind::Rays) {
+        return;
+    }
+
+    for (transform, entity) in focused_cell_query.iter() {
+        let start = transform.translation;
+        for hit in world.cast_rays(entity, transform) {
+            let end = start + (hit.direction * hit.distance).extend(0.0);
+            let color = hit.kind.map_or(Color::GRAY, |k| k.color());
+            lines.line_colored(start, end, 0.0, color);
+        }
+    }
+}
+
+impl<'a> SensorContext<'a> {
+    pub fn new(
+        entity: Entity,
+        transform: &'a Transform,
+        last_bullet_fired: &LastBulletFired,
+        world: &SensorWorld,
+    ) -> Self {
+        let body = BodyState::new(
+            world.energy(entity),
+            world
+                .velocity_query
+                .get(entity)
+                .map_or(Vec2::ZERO, |v| v.linvel),
+            last_bullet_fired,
+        );
+        let pos = transform.translation.truncate();
+        let nearest_food = world
+            .food_tree
+            .0
+            .as_ref()
+            .and_then(|t| t.nearest(&[pos.x, pos.y]))
+            .map_or(Vec2::ZERO, |v| Vec2::from(*v.item));
+        let rays = match SENSORS.contains(&SensorKind::Rays) {
+            true => world.cast_rays(entity, transform),
+            false => Vec::new(),
+        };
+        let nearest_cell = match SENSORS.contains(&SensorKind::NearestCell) {
+            true => world.nearest_cell(entity, pos),
+            false => None,
+        };
+        let visible_food = match SENSORS.contains(&SensorKind::Food) {
+            true => world.visible_food(pos),
+            false => Vec::new(),
+        };
+        let incoming_bullet = match SENSORS.contains(&SensorKind::Bullets) {
+            true => world.incoming_bullet(entity, pos),
+            false => None,
+        };
+
+        Self {
+            nearest_food,
+            visible_food,
+            rays,
+            nearest_cell,
+            incoming_bullet,
+            ..Self::body_only(transform, body)
+        }
+    }
+
+    /// A context with nothing but the cell itself, enough for the body sensors
+    pub fn body_only(transform: &'a Transform, body: BodyState) -> Self {
+        Self {
+            transform,
+            body,
+            nearest_food: Vec2::ZERO,
+            visible_food: Vec::new(),
+            rays: Vec::new(),
+            nearest_cell: None,
+            incoming_bullet: None,
+        }
+    }
+
+    pub fn pos(&self) -> Vec2 {
+        self.transform.translation.truncate()
+    }
+
+    /// Nearest food at any distance, for `calc_fitness`
+    pub fn food_observation(&self) -> FoodObservation {
+        FoodObservation::new(self.transform, self.nearest_food)
+    }
+
+    /// Every food the sensors in `SENSORS` look at
+    pub fn sensed_food(&self) -> Vec<Vec2> {
+        let mut food = self.visible_food.clone();
+        if SENSORS.contains(&SensorKind::NearestFood) && !food.contains(&self.nearest_food) {
+            food.push(self.nearest_food);
+        }
+
+        food
+    }
+}
+
+impl BodyState {
+    pub fn new(energy: f32, velocity: Vec2, last_bullet_fired: &LastBulletFired) -> Self {
+        Self {
+            energy,
+            velocity,
+            cooldown: (BULLET_FIRE_RATE - last_bullet_fired.0.elapsed()).max(0.0),
+        }
+    }
+}
+
+impl<'w, 's> SensorWorld<'w, 's> {
+    /// Rays spread evenly over `VISION_FOV`, from the cell's left to its right
+    /// The cell's own collider is skipped
+    pub fn cast_rays(&self, entity: Entity, transform: &Transform) -> Vec<RayHit> {
+        let pos = transform.translation.truncate();
+        let heading = facing_angle(transform);
+        let filter = QueryFilter::new()
+            .exclude_collider(entity)
+            .exclude_sensors();
+
+        (0..VISION_NUM_RAYS)
+            .map(|i| {
+                let offset = match VISION_NUM_RAYS {
+                    1 => 0.0,
+                    n => VISION_FOV / 2.0 - VISION_FOV * i as f32 / (n - 1) as f32,
+                };
+                let angle = heading + offset;
+                let direction = vec2(angle.cos(), angle.sin());
+                let mut hit = RayHit {
+                    direction,
+                    kind: None,
+                    distance: VISION_RADIUS,
+                };
+
+                if let Some((e, toi)) =
+                    self.rapier_context
+                        .cast_ray(pos, direction, VISION_RADIUS, true, filter)
+                {
+                    if let Some(kind) = self.hit_kind(e) {
+                        hit.kind = Some(kind);
+                        hit.distance = toi;
+                    }
+                }
+                let wall = wall_distance(pos, direction);
+                if wall < hit.distance {
+                    hit.kind = Some(HitKind::Wall);
+                    hit.distance = wall;
+                }
+
+                hit
+            })
+            .collect()
+    }
+
+    pub fn visible_food(&self, pos: Vec2) -> Vec<Vec2> {
+        let Some(tree) = &self.food_tree.0 else {
+            return Vec::new();
+        };
+
+        tree.nearests(&[pos.x, pos.y], FOOD_SENSOR_COUNT)
+            .into_iter()
+            .filter(|v| v.squared_distance <= VISION_RADIUS * VISION_RADIUS)
+            .map(|v| Vec2::from(*v.item))
+            .collect()
+    }
+
+    /// Energy of a cell, zero when it has none yet
+    pub fn energy(&self, entity: Entity) -> f32 {
+        self.cell_query
+            .get(entity)
+            .ok()
+            .and_then(|c| self.energy_map.0.get(&c.0))
+            .map_or(0.0, |(v, _)| *v)
+    }
+
+    /// The cell itself is in the tree too, it's skipped by entity since its position may be stale
+    pub fn nearest_cell(&self, entity: Entity, pos: Vec2) -> Option<NearbyCell> {
+        let tree = self.cell_tree.0.as_ref()?;
+        let nearest = tree
+            .nearests(&[pos.x, pos.y], 2)
+            .into_iter()
+            .find(|v| v.item.1.entity != entity)?;
+        let (point, cell) = nearest.item;
+
+        Some(NearbyCell {
+            pos: Vec2::from(*point),
+            heading: cell.heading,
+            energy: self.energy_map.0.get(&cell.id).map_or(0.0, |(v, _)| *v),
+        })
+    }
+
+    /// Bullets within `VISION_RADIUS` moving towards `pos`, the closest one wins
+    pub fn incoming_bullet(&self, entity: Entity, pos: Vec2) -> Option<IncomingBullet> {
+        let tree = self.bullet_tree.0.as_ref()?;
+        let own_id = self.cell_query.get(entity).ok().map(|c| c.0);
+
+        tree.within_radius(&[pos.x, pos.y], VISION_RADIUS)
+            .into_iter()
+            .filter_map(|(point, bullet)| {
+                let offset = Vec2::from(*point) - pos;
+                let speed_squared = bullet.velocity.length_squared();
+                if speed_squared <= f32::EPSILON {
+                    return None;
+                }
+
+                // Moving away once the closest approach is behind it
+                let time_to_closest = -offset.dot(bullet.velocity) / speed_squared;
+                if time_to_closest <= 0.0 {
+                    return None;
+                }
+
+                Some(IncomingBullet {
+                    pos: Vec2::from(*point),
+                    time_to_closest,
+                    is_own: own_id == Some(bullet.owner),
+                })
+            })
+            .min_by(|a, b| {
+                a.pos
+                    .distance_squared(pos)
+                    .total_cmp(&b.pos.distance_squared(pos))
+            })
+    }
+
+    fn hit_kind(&self, entity: Entity) -> Option<HitKind> {
+        if self.food_query.contains(entity) {
+            Some(HitKind::Food)
+        } else if self.cell_query.contains(entity) {
+            Some(HitKind::Cell)
+        } else if self.bullet_query.contains(entity) {
+            Some(HitKind::Bullet)
+        } else {
+            None
+        }
+    }
+}
+
+impl HitKind {
+    pub const ALL: [HitKind; 4] = [HitKind::Food, HitKind::Cell, HitKind::Bullet, HitKind::Wall];
+
+    pub fn get_label(&self) -> &str {
+        match self {
+            HitKind::Food => "food",
+            HitKind::Cell => "cell",
+            HitKind::Bullet => "bullet",
+            HitKind::Wall => "wall",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            HitKind::Food => Color::RED,
+            HitKind::Cell => Color::ORANGE,
+            HitKind::Bullet => Color::YELLOW,
+            HitKind::Wall => Color::BLACK,
+        }
+    }
+}
+
+impl Sensor for NearestFoodSensor {
+    const NUM_INPUTS: usize = 2;
+
+    fn labels(&self) -> Vec<String> {
+        vec!["dist".to_string(), "target angle".to_string()]
+    }
+
+    /// Distance in vision radii capped at 1, direction from the cell, see `encode_turn`
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        let offset = ctx.nearest_food - ctx.pos();
+        inputs.push((offset.length() / VISION_RADIUS).min(1.0) as f64);
+        inputs.push(encode_turn(offset.y.atan2(offset.x)));
+    }
+}
+
+impl Sensor for HeadingTurnSensor {
+    const NUM_INPUTS: usize = 1;
+
+    fn labels(&self) -> Vec<String> {
+        vec!["cell angle".to_string()]
+    }
+
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        inputs.push(encode_turn(facing_angle(ctx.transform)));
+    }
+}
+
+impl Sensor for FoodSensor {
+    const NUM_INPUTS: usize = FOOD_SENSOR_COUNT * 4;
+
+    fn labels(&self) -> Vec<String> {
+        (0..FOOD_SENSOR_COUNT)
+            .flat_map(|i| {
+                ["seen", "dist", "bearing sin", "bearing cos"]
+                    .into_iter()
+                    .map(move |l| format!("food {} {}", i, l))
+            })
+            .collect()
+    }
+
+    /// Distance in vision radii, bearing relative to the heading, see `encode_angle`
+    /// Slots without food read as unseen, far away and nowhere
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        for i in 0..FOOD_SENSOR_COUNT {
+            let Some(food) = ctx.visible_food.get(i) else {
+                inputs.extend([0.0, 1.0, 0.5, 0.5]);
+                continue;
+            };
+
+            inputs.push(1.0);
+            inputs.push((ctx.pos().distance(*food) / VISION_RADIUS).min(1.0) as f64);
+            inputs.extend(encode_angle(relative_bearing(ctx.transform, *food)));
+        }
+    }
+}
+
+impl Sensor for HeadingSensor {
+    const NUM_INPUTS: usize = 2;
+
+    fn labels(&self) -> Vec<String> {
+        vec!["heading sin".to_string(), "heading cos".to_string()]
+    }
+
+    /// Absolute, a compass rather than a bearing
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        inputs.extend(encode_angle(facing_angle(ctx.transform)));
+    }
+}
+
+impl Sensor for NearestCellSensor {
+    const NUM_INPUTS: usize = 6;
+
+    fn labels(&self) -> Vec<String> {
+        vec![
+            "cell dist".to_string(),
+            "cell bearing sin".to_string(),
+            "cell bearing cos".to_string(),
+            "cell heading sin".to_string(),
+            "cell heading cos".to_string(),
+            "cell energy".to_string(),
+        ]
+    }
+
+    /// Bearing and heading are relative to where the cell faces, see `encode_angle`
+    /// With no other cell around, it reads as far away and nowhere
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        let Some(other) = ctx.nearest_cell else {
+            inputs.extend([1.0, 0.5, 0.5, 0.5, 0.5, 0.0]);
+            return;
+        };
+
+        let heading = wrap_angle(other.heading - facing_angle(ctx.transform));
+        inputs.push((ctx.pos().distance(other.pos) / VISION_RADIUS).min(1.0) as f64);
+        inputs.extend(encode_angle(relative_bearing(ctx.transform, other.pos)));
+        inputs.extend(encode_angle(heading));
+        inputs.push((other.energy / MAX_ENERGY).clamp(0.0, 1.0) as f64);
+    }
+}
+
+impl Sensor for BulletSensor {
+    const NUM_INPUTS: usize = 4;
+
+    fn labels(&self) -> Vec<String> {
+        vec![
+            "bullet time".to_string(),
+            "bullet bearing sin".to_string(),
+            "bullet bearing cos".to_string(),
+            "own bullet".to_string(),
+        ]
+    }
+
+    /// Time is in bullet lifespans, capped at 1, the bearing is relative like `NearestCellSensor`
+    /// With no incoming bullet, it reads as one that's never going to arrive
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        let Some(bullet) = ctx.incoming_bullet else {
+            inputs.extend([1.0, 0.5, 0.5, 0.0]);
+            return;
+        };
+
+        inputs.push((bullet.time_to_closest / BULLET_LIFESPAN).min(1.0) as f64);
+        inputs.extend(encode_angle(relative_bearing(ctx.transform, bullet.pos)));
+        inputs.push(if bullet.is_own { 1.0 } else { 0.0 });
+    }
+}
+
+impl Sensor for EnergySensor {
+    const NUM_INPUTS: usize = 1;
+
+    fn labels(&self) -> Vec<String> {
+        vec!["energy".to_string()]
+    }
+
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        inputs.push((ctx.body.energy / MAX_ENERGY).clamp(0.0, 1.0) as f64);
+    }
+}
+
+impl Sensor for VelocitySensor {
+    const NUM_INPUTS: usize = 2;
+
+    fn labels(&self) -> Vec<String> {
+        vec!["speed ahead".to_string(), "speed left".to_string()]
+    }
+
+    /// Standing still reads 0.5, `MAX_SENSED_SPEED` forwards or left reads 1
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        let heading = facing_angle(ctx.transform);
+        let ahead = vec2(heading.cos(), heading.sin());
+        let left = ahead.perp();
+        for axis in [ahead, left] {
+            let speed = ctx.body.velocity.dot(axis) / MAX_SENSED_SPEED;
+            inputs.push((0.5 + speed / 2.0).clamp(0.0, 1.0) as f64);
+        }
+    }
+}
+
+impl Sensor for CooldownSensor {
+    const NUM_INPUTS: usize = 1;
+
+    fn labels(&self) -> Vec<String> {
+        vec!["cooldown".to_string()]
+    }
+
+    /// 1 right after firing, 0 once it can fire again
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        inputs.push((ctx.body.cooldown / BULLET_FIRE_RATE).clamp(0.0, 1.0) as f64);
+    }
+}
+
+impl Sensor for RaySensor {
+    /// One closeness per hit kind and ray, only the kind that was hit is non zero
+    const NUM_INPUTS: usize = VISION_NUM_RAYS * HitKind::ALL.len();
+
+    fn labels(&self) -> Vec<String> {
+        (0..VISION_NUM_RAYS)
+            .flat_map(|i| {
+                HitKind::ALL
+                    .iter()
+                    .map(move |k| format!("ray {} {}", i, k.get_label()))
+            })
+            .collect()
+    }
+
+    fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        for hit in ctx.rays.iter() {
+            let closeness = (1.0 - hit.distance / VISION_RADIUS).clamp(0.0, 1.0) as f64;
+            for kind in HitKind::ALL {
+                inputs.push(match hit.kind == Some(kind) {
+                    true => closeness,
+                    false => 0.0,
+                });
+            }
+        }
+    }
+}
+
+impl SensorKind {
+    pub const ALL: [SensorKind; 10] = [
+        SensorKind::NearestFood,
+        SensorKind::HeadingTurn,
+        SensorKind::Food,
+        SensorKind::Heading,
+        SensorKind::Rays,
+        SensorKind::NearestCell,
+        SensorKind::Bullets,
+        SensorKind::Energy,
+        SensorKind::Velocity,
+        SensorKind::Cooldown,
+    ];
+
+    pub const fn num_inputs(&self) -> usize {
+        match self {
+            SensorKind::NearestFood => NearestFoodSensor::NUM_INPUTS,
+            SensorKind::HeadingTurn => HeadingTurnSensor::NUM_INPUTS,
+            SensorKind::Food => FoodSensor::NUM_INPUTS,
+            SensorKind::Heading => HeadingSensor::NUM_INPUTS,
+            SensorKind::Rays => RaySensor::NUM_INPUTS,
+            SensorKind::NearestCell => NearestCellSensor::NUM_INPUTS,
+            SensorKind::Bullets => BulletSensor::NUM_INPUTS,
+            SensorKind::Energy => EnergySensor::NUM_INPUTS,
+            SensorKind::Velocity => VelocitySensor::NUM_INPUTS,
+            SensorKind::Cooldown => CooldownSensor::NUM_INPUTS,
+        }
+    }
+
+    /// Inputs of all `sensors` together
+    pub const fn total_inputs(sensors: &[SensorKind]) -> usize {
+        let mut total = 0;
+        let mut i = 0;
+        while i < sensors.len() {
+            total += sensors[i].num_inputs();
+            i += 1;
+        }
+
+        total
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        match self {
+            SensorKind::NearestFood => NearestFoodSensor.labels(),
+            SensorKind::HeadingTurn => HeadingTurnSensor.labels(),
+            SensorKind::Food => FoodSensor.labels(),
+            SensorKind::Heading => HeadingSensor.labels(),
+            SensorKind::Rays => RaySensor.labels(),
+            SensorKind::NearestCell => NearestCellSensor.labels(),
+            SensorKind::Bullets => BulletSensor.labels(),
+            SensorKind::Energy => EnergySensor.labels(),
+            SensorKind::Velocity => VelocitySensor.labels(),
+            SensorKind::Cooldown => CooldownSensor.labels(),
+        }
+    }
+
+    /// Sensors that only look at the cell itself, see `SensorContext::body_only`
+    pub fn is_body(&self) -> bool {
+        matches!(
+            self,
+            SensorKind::Energy | SensorKind::Velocity | SensorKind::Cooldown
+        )
+    }
+
+    pub fn sense(&self, ctx: &SensorContext, inputs: &mut Vec<f64>) {
+        let start = inputs.len();
+        match self {
+            SensorKind::NearestFood => NearestFoodSensor.sense(ctx, inputs),
+            SensorKind::HeadingTurn => HeadingTurnSensor.sense(ctx, inputs),
+            SensorKind::Food => FoodSensor.sense(ctx, inputs),
+            SensorKind::Heading => HeadingSensor.sense(ctx, inputs),
+            SensorKind::Rays => RaySensor.sense(ctx, inputs),
+            SensorKind::NearestCell => NearestCellSensor.sense(ctx, inputs),
+            SensorKind::Bullets => BulletSensor.sense(ctx, inputs),
+            SensorKind::Energy => EnergySensor.sense(ctx, inputs),
+            SensorKind::Velocity => VelocitySensor.sense(ctx, inputs),
+            SensorKind::Cooldown => CooldownSensor.sense(ctx, inputs),
+        }
+        debug_assert_eq!(inputs.len() - start, self.num_inputs(), "{:?}", self);
+    }
+}
+
+/// Brain inputs of a cell, every sensor in `SENSORS` in order
+pub fn sense_all(ctx: &SensorContext, inputs: &mut Vec<f64>) {
+    inputs.clear();
+    for sensor in SENSORS.iter() {
+        sensor.sense(ctx, inputs);
+    }
+}
+
+/// Labelled inputs of the body sensors in `SENSORS`
+pub fn body_inputs(ctx: &SensorContext) -> Vec<(String, f64)> {
+    let mut labelled = Vec::new();
+    let mut inputs = Vec::new();
+    for sensor in SENSORS.iter().filter(|s| s.is_body()) {
+        inputs.clear();
+        sensor.sense(ctx, &mut inputs);
+        labelled.extend(sensor.labels().into_iter().zip(inputs.iter().copied()));
+    }
+
+    labelled
+}
+
+/// What each brain input means, in order
+pub fn input_labels() -> Vec<String> {
+    SENSORS.iter().flat_map(|s| s.labels()).collect()
+}
+
+/// Distance along `direction` to the edge of the world, zero outside of it
+fn wall_distance(pos: Vec2, direction: Vec2) -> f32 {
+    let half = vec2(W as f32, H as f32) / 2.0;
+    if pos.x.abs() >= half.x || pos.y.abs() >= half.y {
+        return 0.0;
+    }
+
+    let axis = |p: f32, d: f32, h: f32| {
+        if d > 0.0 {
+            (h - p) / d
+        } else if d < 0.0 {
+            (-h - p) / d
+        } else {
+            f32::INFINITY
+        }
+    };
+
+    axis(pos.x, direction.x, half.x).min(axis(pos.y, direction.y, half.y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Something in reach of every sensor
+    fn busy_context(transform: &Transform) -> SensorContext<'_> {
+        let body = BodyState {
+            energy: MAX_ENERGY / 2.0,
+            velocity: vec2(10.0, -5.0),
+            cooldown: BULLET_FIRE_RATE / 2.0,
+        };
+        let food = vec2(VISION_RADIUS / 2.0, 0.0);
+
+        SensorContext {
+            nearest_food: food,
+            visible_food: vec![food],
+            rays: (0..VISION_NUM_RAYS)
+                .map(|_| RayHit {
+                    direction: Vec2::Y,
+                    kind: Some(HitKind::Cell),
+                    distance: VISION_RADIUS / 4.0,
+                })
+                .collect(),
+            nearest_cell: Some(NearbyCell {
+                pos: vec2(0.0, VISION_RADIUS),
+                heading: 1.0,
+                energy: MAX_ENERGY,
+            }),
+            incoming_bullet: Some(IncomingBullet {
+                pos: vec2(-10.0, 0.0),
+                time_to_closest: 0.1,
+                is_own: false,
+            }),
+            ..SensorContext::body_only(transform, body)
+        }
+    }
+
+    #[test]
+    fn sensors_write_their_declared_inputs() {
+        let transform = Transform::default();
+        let ctx = busy_context(&transform);
+        let empty_ctx = SensorContext::body_only(&transform, BodyState::default());
+
+        for kind in SensorKind::ALL {
+            assert_eq!(kind.labels().len(), kind.num_inputs(), "{:?}", kind);
+            let mut inputs = Vec::new();
+            kind.sense(&ctx, &mut inputs);
+            assert_eq!(inputs.len(), kind.num_inputs(), "{:?}", kind);
+            assert!(inputs.iter().all(|v| (0.0..=1.0).contains(v)), "{:?}", kind);
+
+            if kind != SensorKind::Rays {
+                inputs.clear();
+                kind.sense(&empty_ctx, &mut inputs);
+                assert_eq!(inputs.len(), kind.num_inputs(), "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn configured_sensors_fill_the_input_layer() {
+        let transform = Transform::default();
+        let mut inputs = Vec::new();
+        sense_all(&busy_context(&transform), &mut inputs);
+
+        assert_eq!(SensorKind::total_inputs(&SENSORS), NUM_INPUT_NODES);
+        assert_eq!(input_labels().len(), NUM_INPUT_NODES);
+        assert_eq!(inputs.len(), NUM_INPUT_NODES);
+        assert_eq!(NET_ARCH[0], NUM_INPUT_NODES);
+    }
+
+    #[test]
+    fn original_inputs_keep_their_encoding() {
+        // Facing up, food far below, the way the first brains saw it
+        let transform = Transform::default();
+        let ctx = SensorContext {
+            nearest_food: vec2(0.0, -2.0 * VISION_RADIUS),
+            ..SensorContext::body_only(&transform, BodyState::default())
+        };
+        let mut inputs = Vec::new();
+        SensorKind::NearestFood.sense(&ctx, &mut inputs);
+        SensorKind::HeadingTurn.sense(&ctx, &mut inputs);
+
+        let expected = [1.0, 0.75, 0.25];
+        for (value, expected) in inputs.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-6, "{:?}", inputs);
+        }
+    }
+
+    #[test]
+    fn wall_distance_reaches_the_edge() {
+        let half_w = W as f32 / 2.0;
+        assert!((wall_distance(Vec2::ZERO, Vec2::X) - half_w).abs() < 1e-3);
+        assert_eq!(wall_distance(vec2(half_w + 1.0, 0.0), Vec2::X), 0.0);
+    }
+}
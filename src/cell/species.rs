@@ -0,0 +1,156 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{rng::ResetRunEvent, trackers::on_sim_timer, *};
+
+use super::{user::UserControlledCell, Brain, Cell};
+
+pub struct CellSpeciesPlugin;
+
+/// Species a cell was put in at the last clustering
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SpeciesId(pub u32);
+
+pub struct Species {
+    pub id: u32,
+    pub color: Color,
+    pub num_members: usize,
+    /// Cells are compared against this brain, one of the members at the last clustering
+    representative: Brain,
+}
+
+/// Every living species, oldest first
+/// Ids are never reused, a species keeps its id and colour for as long as it has members
+#[derive(Resource)]
+pub struct SpeciesMap {
+    pub species: Vec<Species>,
+    pub threshold: f64,
+    next_id: u32,
+}
+
+impl Plugin for CellSpeciesPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SpeciesMap::new())
+            .add_systems(
+                Update,
+                update_species.run_if(on_sim_timer(SPECIES_UPDATE_INTERVAL_SECS)),
+            )
+            .add_systems(PreUpdate, clear_species.run_if(on_event::<ResetRunEvent>()));
+    }
+}
+
+/// The threshold adapts to the population, it starts over too
+fn clear_species(mut species_map: ResMut<SpeciesMap>) {
+    *species_map = SpeciesMap::new();
+}
+
+/// Puts every cell in the first species whose representative is close enough,
+/// a cell stays in its current species while it's still close to it
+fn update_species(
+    mut commands: Commands,
+    mut species_map: ResMut<SpeciesMap>,
+    cell_query: Query<
+        (&Brain, Option<&SpeciesId>, Entity),
+        (With<Cell>, Without<UserControlledCell>),
+    >,
+) {
+    let species_map = &mut *species_map;
+    species_map
+        .species
+        .iter_mut()
+        .for_each(|s| s.num_members = 0);
+    let mut representatives = HashMap::new();
+
+    for (brain, species_id, entity) in cell_query.iter() {
+        let index = species_map.assign(brain, species_id.copied());
+        let species = &mut species_map.species[index];
+        species.num_members += 1;
+        representatives
+            .entry(species.id)
+            .or_insert_with(|| brain.clone());
+
+        if species_id.map(|s| s.0) != Some(species.id) {
+            commands.entity(entity).insert(SpeciesId(species.id));
+        }
+    }
+
+    species_map.species.retain(|s| s.num_members > 0);
+    for species in species_map.species.iter_mut() {
+        if let Some(brain) = representatives.remove(&species.id) {
+            species.representative = brain;
+        }
+    }
+
+    // Loosen or tighten the threshold to keep roughly the target number of species
+    let num_species = species_map.species.len();
+    if num_species > SPECIES_TARGET_COUNT {
+        species_map.threshold += SPECIES_THRESHOLD_STEP;
+    } else if num_species < SPECIES_TARGET_COUNT {
+        species_map.threshold =
+            (species_map.threshold - SPECIES_THRESHOLD_STEP).max(SPECIES_THRESHOLD_STEP);
+    }
+}
+
+/// Stable colour of a species, hues are spread by the golden angle
+pub fn species_color(id: u32) -> Color {
+    Color::hsl((id as f32 * 137.508) % 360.0, 0.65, 0.55)
+}
+
+impl SpeciesMap {
+    fn new() -> Self {
+        Self {
+            species: Vec::new(),
+            threshold: SPECIES_DISTANCE_THRESHOLD,
+            next_id: 0,
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Species> {
+        self.species.iter().find(|s| s.id == id)
+    }
+
+    /// Energy of a cell divided between every member of its species
+    /// Cells that haven't been clustered yet keep all of it
+    pub fn shared_energy(&self, energy: f32, species_id: Option<&SpeciesId>) -> f32 {
+        let num_members = species_id
+            .and_then(|s| self.get(s.0))
+            .map(|s| s.num_members)
+            .unwrap_or(1);
+
+        energy / num_members.max(1) as f32
+    }
+
+    /// Index of the species `brain` belongs to, starts a new one when none is close enough
+    fn assign(&mut self, brain: &Brain, current: Option<SpeciesId>) -> usize {
+        let current_index = current.and_then(|c| self.species.iter().position(|s| s.id == c.0));
+        if let Some(index) = current_index {
+            if brain.distance(&self.species[index].representative) < self.threshold {
+                return index;
+            }
+        }
+
+        let mut closest = None;
+        for (index, species) in self.species.iter().enumerate() {
+            let distance = brain.distance(&species.representative);
+            if distance < self.threshold {
+                return index;
+            }
+            if closest.map_or(true, |(_, d)| distance < d) {
+                closest = Some((index, distance));
+            }
+        }
+
+        match closest {
+            Some((index, _)) if self.species.len() >= SPECIES_MAX_COUNT => index,
+            _ => {
+                self.species.push(Species {
+                    id: self.next_id,
+                    color: species_color(self.next_id),
+                    num_members: 0,
+                    representative: brain.clone(),
+                });
+                self.next_id += 1;
+                self.species.len() - 1
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    es::OpenAiEs,
+    nn::Net,
+    rng::{ResetRunEvent, SimRng},
+    settings::DynamicSettings,
+    trackers::{on_sim_timer, InstantTracker},
+    *,
+};
+
+use super::{
+    bundle::CellBundle,
+    energy::EnergyMap,
+    lineage::{CellDeathEvent, DeathCause},
+    user::UserControlledCell,
+    Brain, Cell, CellId,
+};
+
+pub struct CellTrainerPlugin;
+
+/// How new brains are found
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrainerMode {
+    /// Steady state genetic algorithm, cells replicate based on their energy
+    Genetic,
+    /// OpenAI-ES over the weights of a dense brain, every candidate is scored
+    /// by its energy at the end of a fixed evaluation window
+    Es,
+}
+
+/// Cell evaluating the `n`th candidate of the current ES generation
+#[derive(Component)]
+pub struct EsCandidate(pub usize);
+
+#[derive(Resource, Default)]
+pub struct EsTrainer {
+    es: Option<OpenAiEs>,
+    /// Net the candidate weights are loaded into
+    template: Option<Net>,
+    generation_start: InstantTracker,
+    /// Cell of every candidate in `ask` order, None when its weights couldn't be loaded
+    candidates: Vec<Option<(Entity, u32)>>,
+    /// Best and mean fitness of every finished generation
+    pub history: Vec<(f32, f32)>,
+    /// Why the last generation didn't update the search, shown in the Trainer section
+    pub last_error: Option<String>,
+}
+
+impl Plugin for CellTrainerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EsTrainer::default())
+            .add_systems(Update, update_es_trainer.run_if(on_sim_timer(0.5)))
+            .add_systems(
+                PreUpdate,
+                clear_es_trainer.run_if(on_event::<ResetRunEvent>()),
+            );
+    }
+}
+
+/// The candidates are gone with the old run, the next update starts a new search
+fn clear_es_trainer(mut trainer: ResMut<EsTrainer>) {
+    *trainer = EsTrainer::default();
+}
+
+fn update_es_trainer(
+    mut commands: Commands,
+    mut trainer: ResMut<EsTrainer>,
+    mut cell_id: ResMut<CellId>,
+    mut sim_rng: ResMut<SimRng>,
+    settings: Res<DynamicSettings>,
+    energy_map: Res<EnergyMap>,
+    asset_server: Res<AssetServer>,
+    mut deaths: EventWriter<CellDeathEvent>,
+    cell_query: Query<(&Cell, Entity), (With<Cell>, Without<UserControlledCell>)>,
+) {
+    if settings.trainer != TrainerMode::Es {
+        // The candidates stay around as regular cells
+        trainer.stop();
+        return;
+    }
+
+    let sim_rng = &mut *sim_rng;
+    if !trainer.is_running() {
+        let net = match Net::with_layer_kinds(
+            NET_ARCH.to_vec(),
+            NET_ACTIVATIONS.to_vec(),
+            NET_LAYER_KINDS.to_vec(),
+            &mut sim_rng.brains,
+        ) {
+            Ok(net) => net,
+            Err(err) => {
+                error!("Can't start the ES trainer: {}", err);
+                return;
+            }
+        };
+
+        // Candidates are only comparable in a world of their own
+        for (cell, entity) in cell_query.iter() {
+            deaths.send(CellDeathEvent {
+                cell_id: cell.0,
+                cause: DeathCause::Trainer,
+            });
+            commands.entity(entity).despawn();
+        }
+        trainer.es = Some(OpenAiEs::new(
+            net.weights(),
+            ES_SIGMA,
+            ES_LEARNING_RATE,
+            ES_POPULATION_SIZE,
+        ));
+        trainer.template = Some(net);
+        trainer.spawn_generation(&mut commands, &mut cell_id, &asset_server, sim_rng);
+        return;
+    }
+
+    if !trainer
+        .generation_start
+        .elapsed_past(ES_EVALUATION_WINDOW_SECS)
+    {
+        return;
+    }
+
+    // Cells that died during the window have lost their energy,
+    // candidates that never got a cell rank below every other
+    let fitness: Vec<f64> = trainer
+        .candidates
+        .iter()
+        .map(|candidate| match candidate {
+            Some((_, id)) => energy_map.0.get(id).map_or(0.0, |(v, _)| *v as f64),
+            None => f64::NEG_INFINITY,
+        })
+        .collect();
+    for (entity, id) in trainer.candidates.iter().flatten() {
+        if let Some(mut entity) = commands.get_entity(*entity) {
+            deaths.send(CellDeathEvent {
+                cell_id: *id,
+                cause: DeathCause::Trainer,
+            });
+            entity.despawn();
+        }
+    }
+
+    let scored: Vec<f64> = fitness.iter().copied().filter(|f| f.is_finite()).collect();
+    let best = scored.iter().copied().fold(0.0, f64::max);
+    let mean = scored.iter().sum::<f64>() / scored.len().max(1) as f64;
+    trainer.history.push((best as f32, mean as f32));
+    if let Some(es) = trainer.es.as_mut() {
+        match es.tell(&fitness) {
+            Ok(()) => {
+                info!(
+                    "ES generation {}: best {:.1}, mean {:.1}",
+                    es.generation(),
+                    best,
+                    mean
+                );
+                trainer.last_error = None;
+            }
+            Err(err) => {
+                error!("ES generation {} skipped: {}", es.generation(), err);
+                trainer.last_error = Some(err.to_string());
+            }
+        }
+    }
+
+    trainer.spawn_generation(&mut commands, &mut cell_id, &asset_server, sim_rng);
+}
+
+impl TrainerMode {
+    pub const ALL: [TrainerMode; 2] = [TrainerMode::Genetic, TrainerMode::Es];
+
+    pub fn get_label(&self) -> &str {
+        match self {
+            TrainerMode::Genetic => "Genetic",
+            TrainerMode::Es => "OpenAI-ES",
+        }
+    }
+}
+
+impl EsTrainer {
+    pub fn is_running(&self) -> bool {
+        self.es.is_some()
+    }
+
+    /// Finished generations
+    pub fn generation(&self) -> usize {
+        self.es.as_ref().map_or(0, |es| es.generation())
+    }
+
+    /// Seconds until the current generation is scored
+    pub fn time_left(&self) -> f32 {
+        (ES_EVALUATION_WINDOW_SECS - self.generation_start.elapsed()).max(0.0)
+    }
+
+    fn stop(&mut self) {
+        self.es = None;
+        self.template = None;
+        self.candidates.clear();
+    }
+
+    fn spawn_generation(
+        &mut self,
+        commands: &mut Commands,
+        cell_id: &mut CellId,
+        asset_server: &AssetServer,
+        sim_rng: &mut SimRng,
+    ) {
+        let (Some(es), Some(template)) = (self.es.as_mut(), self.template.as_ref()) else {
+            return;
+        };
+
+        self.candidates.clear();
+        for (i, weights) in es.ask(&mut sim_rng.brains).into_iter().enumerate() {
+            let mut net = template.clone();
+            if let Err(err) = net.set_weights(&weights) {
+                error!("Skipping ES candidate {}: {}", i, err);
+                self.candidates.push(None);
+                continue;
+            }
+
+            let x = sim_rng.cells.gen_range(-(W as f32) / 2.0..W as f32 / 2.0);
+            let y = sim_rng.cells.gen_range(-(H as f32) / 2.0..H as f32 / 2.0);
+            cell_id.0 += 1;
+            let entity = commands
+                .spawn((
+                    CellBundle::new(
+                        x,
+                        y,
+                        cell_id.0,
+                        Brain::Dense(net),
+                        CELL_SPRITE,
+                        asset_server,
+                        &mut sim_rng.cells,
+                    ),
+                    EsCandidate(i),
+                ))
+                .id();
+            self.candidates.push(Some((entity, cell_id.0)));
+        }
+
+        self.generation_start.set_instant_now();
+    }
+}
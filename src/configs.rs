@@ -1,3 +1,10 @@
+use std::f32::consts::PI;
+
+use crate::{
+    cell::{sensor::SensorKind, trainer::TrainerMode, ActionMode, BrainKind, ExtraAction},
+    nn::{Activation, Crossover, LayerKind},
+};
+
 // Windowing
 pub const WW: usize = 900;
 pub const WH: usize = 700;
@@ -6,19 +13,50 @@ pub const BG_COLOR: (u8, u8, u8) = (195, 232, 208);
 // Environment
 pub const W: usize = 10000;
 pub const H: usize = 10000;
+/// Simulated seconds per frame, timers count frames so a seed replays the same run
+/// Slow frames slow the simulation down instead of letting it skip ahead
+pub const SIM_TICK_SECS: f32 = 1.0 / 60.0;
 
 // GUI
 pub const MAX_GRAPH_POINTS: usize = 1500;
 pub const NN_NODE_SIZE: f32 = 10.0;
+pub const NN_VIZ_HEIGHT: f32 = 450.0;
+/// Space kept on both sides of the network for the input and output labels
+pub const NN_LABEL_WIDTH: f32 = 60.0;
+/// Layers with more nodes than this are drawn without labels, they would overlap
+pub const NN_MAX_LABELED_NODES: usize = 24;
+pub const NN_POSITIVE_WEIGHT_COLOR: (u8, u8, u8) = (90, 200, 90);
+pub const NN_NEGATIVE_WEIGHT_COLOR: (u8, u8, u8) = (220, 80, 80);
+pub const MAX_LISTED_SPECIES: usize = 10;
 
 // Cell
 pub const NUM_CELLS: usize = 4000;
 pub const CELL_SPEED: f32 = 1.0;
+/// Radians a cell turns per update at most
+pub const CELL_TURN_RATE: f32 = 0.5;
+pub const CELL_LINEAR_DAMPING: f32 = 2.0;
+/// Share of `CELL_SPEED` at full strafe and full reverse
+pub const STRAFE_THRUST: f32 = 0.5;
+pub const REVERSE_THRUST: f32 = 0.5;
+/// Linear damping added on top of `CELL_LINEAR_DAMPING` at full brake
+pub const BRAKE_DAMPING: f32 = 8.0;
+/// Energy taken per update at full strength
+pub const STRAFE_ENERGY_COST: f32 = 1.0;
+pub const BRAKE_ENERGY_COST: f32 = 0.5;
+pub const REVERSE_ENERGY_COST: f32 = 1.0;
 pub const BASE_ENERGY: f32 = 100.0;
 pub const ENERGY_UPDATE_INTERVAL_SECS: f32 = 1.0;
 pub const ENERGY_DECAY_RATE: f32 = 5.0;
 pub const UPDATE_INTERVAL: f32 = 0.5;
 pub const VISION_RADIUS: f32 = 200.0;
+/// Rays of the `Rays` sensor, spread over `VISION_FOV` radians around the heading
+pub const VISION_NUM_RAYS: usize = 8;
+pub const VISION_FOV: f32 = PI;
+/// Food items the `Food` sensor reports, closest first
+pub const FOOD_SENSOR_COUNT: usize = 3;
+pub const CELL_TREE_REFRESH_RATE_SECS: f32 = 0.5;
+/// Speeds past this read the same to the `Velocity` sensor
+pub const MAX_SENSED_SPEED: f32 = 100.0;
 pub const MAX_ENERGY: f32 = 4000.0;
 pub const IS_USER_ENABLED: bool = false;
 pub const CELL_SPRITE: &str = "turret.png";
@@ -41,12 +79,88 @@ pub const FOOD_TREE_REFRESH_RATE_SECS: f32 = 1.0;
 pub const FOOD_SPRITE: &str = "red-dot.png";
 
 // NN
-pub const NUM_INPUT_NODES: usize = 3;
+/// What the brains sense, in input order
+/// `NearestFood` with `HeadingTurn` are the inputs brains had before sensors were pluggable
+/// Add `Rays`, `NearestCell`, `Bullets`, `Energy`, `Velocity` or `Cooldown` to opt in,
+/// `Rays` is the costly one, it casts `VISION_NUM_RAYS` rays per cell update
+pub const SENSORS: [SensorKind; 2] = [SensorKind::Food, SensorKind::Heading];
+pub const NUM_INPUT_NODES: usize = SensorKind::total_inputs(&SENSORS);
 pub const NUM_HIDDEN_NODES: usize = 8;
-pub const NUM_OUTPUT_NODES: usize = 4;
+/// Optional actions, each one adds an output after the base ones
+pub const EXTRA_ACTIONS: [ExtraAction; 3] = [
+    ExtraAction::Strafe,
+    ExtraAction::Brake,
+    ExtraAction::Reverse,
+];
+pub const NUM_BASE_OUTPUT_NODES: usize = 4;
+pub const NUM_OUTPUT_NODES: usize = NUM_BASE_OUTPUT_NODES + EXTRA_ACTIONS.len();
 pub const NET_ARCH: [usize; 3] = [NUM_INPUT_NODES, NUM_HIDDEN_NODES, NUM_OUTPUT_NODES];
-pub const BRAIN_MUTATION_RATE: f32 = 0.1;
-pub const BRAIN_MUTATION_VARIATION: f32 = 0.1;
+/// Activation of each layer after the inputs, used for new random brains
+pub const NET_ACTIVATIONS: [Activation; 2] = [Activation::Sigmoid, Activation::Sigmoid];
+/// Kind of each layer after the inputs, `Elman` layers give cells a short memory
+pub const NET_LAYER_KINDS: [LayerKind; 2] = [LayerKind::Dense, LayerKind::Dense];
+/// Starting mutation rate and variation, every brain then evolves its own
+pub const BRAIN_MUTATION_RATE: f64 = 0.1;
+pub const BRAIN_MUTATION_VARIATION: f64 = 0.1;
+/// Learning rate of the log-normal self-adaptation of the mutation parameters
+pub const BRAIN_SELF_ADAPTATION_RATE: f64 = 0.2;
+pub const BRAIN_MUTATION_RATE_LIMITS: (f64, f64) = (0.005, 1.0);
+pub const BRAIN_MUTATION_VARIATION_LIMITS: (f64, f64) = (0.001, 2.0);
+pub const BRAIN_CROSSOVER: Crossover = Crossover::None;
+pub const BRAIN_KIND: BrainKind = BrainKind::Dense;
+pub const TRAINER_MODE: TrainerMode = TrainerMode::Genetic;
+/// `Discrete` is what brains evolved before `Continuous` existed
+pub const ACTION_MODE: ActionMode = ActionMode::Discrete;
+
+// NEAT
+pub const NEAT_ADD_CONNECTION_RATE: f32 = 0.05;
+pub const NEAT_ADD_NODE_RATE: f32 = 0.03;
+pub const NEAT_TOGGLE_CONNECTION_RATE: f32 = 0.01;
+/// Weights of excess/disjoint genes and of the weight difference in the genome distance
+pub const NEAT_DISJOINT_COEFFICIENT: f64 = 1.0;
+pub const NEAT_WEIGHT_COEFFICIENT: f64 = 0.4;
+
+// Species
+pub const SPECIES_UPDATE_INTERVAL_SECS: f32 = 1.0;
+/// Starting brain distance under which two cells belong to the same species
+pub const SPECIES_DISTANCE_THRESHOLD: f64 = 0.5;
+/// The threshold is nudged every update to get close to this many species
+pub const SPECIES_TARGET_COUNT: usize = 10;
+pub const SPECIES_THRESHOLD_STEP: f64 = 0.02;
+/// Past this, cells join their closest species instead of starting a new one
+pub const SPECIES_MAX_COUNT: usize = 30;
+pub const SPECIES_FITNESS_SHARING: bool = true;
+
+// Evolution strategies
+pub const ES_POPULATION_SIZE: usize = 200;
+/// Candidates are scored by their energy once this window is over
+pub const ES_EVALUATION_WINDOW_SECS: f32 = 30.0;
+pub const ES_SIGMA: f64 = 0.1;
+pub const ES_LEARNING_RATE: f64 = 0.05;
+
+// Behaviour cloning
+pub const DEMOS_DIR: &str = "demos";
+pub const BC_EPOCHS: usize = 200;
+pub const BC_LEARNING_RATE: f64 = 0.05;
+/// Targets are kept this far inside 0..1, saturated outputs stop learning
+pub const BC_TARGET_MARGIN: f64 = 0.05;
+/// Cells spawned with a cloned brain, all but the first are mutated copies
+pub const BC_INJECT_COUNT: usize = 20;
+
+// Hall of fame
+pub const HALL_OF_FAME_DIR: &str = "hall_of_fame";
+/// Entries kept per criterion, an entry can be in the top of several
+pub const HALL_OF_FAME_SIZE: usize = 20;
+pub const HALL_OF_FAME_UPDATE_INTERVAL_SECS: f32 = 1.0;
+pub const HALL_OF_FAME_SAVE_INTERVAL_SECS: f32 = 30.0;
+pub const HALL_OF_FAME_INJECT_COUNT: usize = 10;
+
+pub const BRAINS_DIR: &str = "brains";
+pub const LINEAGE_DIR: &str = "lineage";
+/// Records kept before extinct branches, then the oldest ancestors, are dropped
+pub const LINEAGE_MAX_RECORDS: usize = 20000;
+/// Cells per task when the brains are evaluated in parallel
+pub const BRAIN_BATCH_CHUNK_SIZE: usize = 256;
 
 /// Collision groups
 /// bit 1 - Cells
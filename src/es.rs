@@ -0,0 +1,159 @@
+use std::fmt;
+
+use rand::Rng;
+
+use crate::nn::sample_normal;
+
+/// OpenAI style evolution strategy over a flat parameter vector
+/// Candidates are sampled in mirrored pairs around the mean, which is then moved
+/// along the rank weighted average of the noise
+pub struct OpenAiEs {
+    mean: Vec<f64>,
+    sigma: f64,
+    learning_rate: f64,
+    population_size: usize,
+    noise: Vec<Vec<f64>>,
+    generation: usize,
+}
+
+/// `tell` needs one fitness per candidate of the last `ask`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FitnessSizeError {
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl OpenAiEs {
+    /// `population_size` is rounded up to an even number, candidates come in pairs
+    pub fn new(mean: Vec<f64>, sigma: f64, learning_rate: f64, population_size: usize) -> Self {
+        Self {
+            mean,
+            sigma,
+            learning_rate,
+            population_size: population_size.max(2).next_multiple_of(2),
+            noise: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn mean(&self) -> &[f64] {
+        &self.mean
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    pub fn population_size(&self) -> usize {
+        self.population_size
+    }
+
+    /// Candidates of the next generation, to be scored and handed back to `tell` in order
+    pub fn ask(&mut self, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+        self.noise.clear();
+        for _ in 0..self.population_size / 2 {
+            let eps: Vec<f64> = self.mean.iter().map(|_| sample_normal(rng)).collect();
+            self.noise.push(eps.iter().map(|e| -e).collect());
+            self.noise.push(eps);
+        }
+
+        self.noise
+            .iter()
+            .map(|eps| {
+                self.mean
+                    .iter()
+                    .zip(eps.iter())
+                    .map(|(m, e)| m + self.sigma * e)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Moves the mean using the fitness of every candidate from the last `ask`
+    /// Fitness is turned into centered ranks first, so only the ordering matters
+    /// The mean doesn't move when the fitness doesn't line up with the candidates
+    pub fn tell(&mut self, fitness: &[f64]) -> Result<(), FitnessSizeError> {
+        if fitness.len() != self.noise.len() || self.noise.is_empty() {
+            return Err(FitnessSizeError {
+                expected: self.noise.len(),
+                found: fitness.len(),
+            });
+        }
+
+        let weights = centered_ranks(fitness);
+        let scale = self.learning_rate / (self.noise.len() as f64 * self.sigma);
+        for (i, m) in self.mean.iter_mut().enumerate() {
+            let gradient: f64 = self
+                .noise
+                .iter()
+                .zip(weights.iter())
+                .map(|(eps, w)| w * eps[i])
+                .sum();
+            *m += scale * gradient;
+        }
+
+        self.noise.clear();
+        self.generation += 1;
+        Ok(())
+    }
+}
+
+impl fmt::Display for FitnessSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Expected {} fitness values, one per candidate, found {}",
+            self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for FitnessSizeError {}
+
+/// Ranks scaled to -0.5..=0.5, ties keep their order
+fn centered_ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let max_rank = (values.len().max(2) - 1) as f64;
+    for (rank, index) in order.into_iter().enumerate() {
+        ranks[index] = rank as f64 / max_rank - 0.5;
+    }
+
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn tell_rejects_fitness_of_the_wrong_size() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut es = OpenAiEs::new(vec![0.0; 3], 0.1, 0.01, 4);
+        let candidates = es.ask(&mut rng);
+
+        let err = es.tell(&[1.0; 3]).unwrap_err();
+        assert_eq!(
+            err,
+            FitnessSizeError {
+                expected: candidates.len(),
+                found: 3
+            }
+        );
+        assert_eq!(es.generation(), 0);
+        assert_eq!(es.mean(), &[0.0; 3]);
+
+        es.tell(&[1.0, 2.0, 3.0, 4.0]).unwrap();
+        assert_eq!(es.generation(), 1);
+    }
+
+    #[test]
+    fn unscored_candidates_rank_last() {
+        let ranks = centered_ranks(&[1.0, f64::NEG_INFINITY, -2.0]);
+        assert_eq!(ranks, vec![0.5, -0.5, 0.0]);
+    }
+}
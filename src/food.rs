@@ -1,11 +1,14 @@
-use std::time::Duration;
-
-use bevy::{prelude::*, time::common_conditions::on_timer};
+use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 use kd_tree::KdTree;
 use rand::Rng;
 
-use crate::{settings::DynamicSettings, *};
+use crate::{
+    rng::{ResetRunEvent, SimRng},
+    settings::DynamicSettings,
+    trackers::on_sim_timer,
+    *,
+};
 
 pub struct FoodPlugin;
 
@@ -31,17 +34,26 @@ impl Plugin for FoodPlugin {
             .add_systems(Startup, setup)
             .add_systems(
                 Update,
-                spawn_food.run_if(on_timer(Duration::from_secs_f32(
-                    FOOD_REFRESH_INTERVAL_SECS,
-                ))),
+                spawn_food.run_if(on_sim_timer(FOOD_REFRESH_INTERVAL_SECS)),
             )
             .add_systems(
                 Update,
-                reload_food_kd_tree.run_if(on_timer(Duration::from_secs_f32(
-                    FOOD_TREE_REFRESH_RATE_SECS,
-                ))),
-            );
+                reload_food_kd_tree.run_if(on_sim_timer(FOOD_TREE_REFRESH_RATE_SECS)),
+            )
+            .add_systems(PreUpdate, clear_food.run_if(on_event::<ResetRunEvent>()))
+            .add_systems(Update, setup.run_if(on_event::<ResetRunEvent>()));
+    }
+}
+
+fn clear_food(
+    mut commands: Commands,
+    mut food_tree: ResMut<FoodTree>,
+    food_query: Query<Entity, With<Food>>,
+) {
+    for entity in food_query.iter() {
+        commands.entity(entity).despawn();
     }
+    food_tree.0 = None;
 }
 
 fn setup(
@@ -49,8 +61,9 @@ fn setup(
     asset_server: Res<AssetServer>,
     food_query: Query<With<Food>>,
     settings: Res<DynamicSettings>,
+    sim_rng: ResMut<SimRng>,
 ) {
-    spawn_food(commands, asset_server, settings, food_query);
+    spawn_food(commands, asset_server, settings, food_query, sim_rng);
 }
 
 fn spawn_food(
@@ -58,8 +71,9 @@ fn spawn_food(
     asset_server: Res<AssetServer>,
     settings: Res<DynamicSettings>,
     food_query: Query<With<Food>>,
+    mut sim_rng: ResMut<SimRng>,
 ) {
-    let mut rng = rand::thread_rng();
+    let rng = &mut sim_rng.food;
     let num_food = food_query.iter().len();
     let food_diff = 500;
     if num_food > (settings.num_food - food_diff) {
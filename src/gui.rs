@@ -1,12 +1,10 @@
-use std::time::Duration;
-
-use bevy::{math::vec3, prelude::*, time::common_conditions::on_timer};
+use bevy::{ecs::system::SystemParam, math::vec3, prelude::*};
 use bevy_egui::{
     egui::{
         self,
-        epaint::CircleShape,
+        epaint::{text::Fonts, CircleShape},
         plot::{Line, Plot, PlotPoints},
-        pos2, Color32, Shape, Stroke,
+        pos2, Align2, Color32, FontId, Shape, Stroke,
     },
     EguiContexts, EguiPlugin, EguiSettings,
 };
@@ -15,13 +13,25 @@ use crate::{
     bullet::Bullet,
     camera::FollowCamera,
     cell::{
+        demo::{CloneDemoEvent, DemoRecorder},
         energy::EnergyMap,
-        focus::{FocusedCell, FocusedCellNet, FocusedCellStats, UnFocusCellEvent},
-        Cell,
+        focus::{
+            ExportFocusedBrainEvent, FocusedCell, FocusedCellNet, FocusedCellStats,
+            UnFocusCellEvent,
+        },
+        hall_of_fame::{FameCriterion, HallOfFame, InjectFameEntryEvent},
+        lineage::{ExportLineageEvent, Lineage, LineageFormat},
+        output_labels,
+        sensor::input_labels,
+        species::{species_color, SpeciesMap},
+        trainer::{EsTrainer, TrainerMode},
+        ActionMode, Brain, Cell, CellId, ExtraAction,
     },
     food::{Food, FoodTree},
+    nn::{Crossover, NetFormat},
+    rng::{ResetRunEvent, SimRng},
     settings::{DynamicSettings, SimSettings},
-    trackers::{BirthTs, InstantTracker},
+    trackers::{on_sim_timer, BirthTs, InstantTracker},
     *,
 };
 
@@ -32,6 +42,7 @@ enum Panel {
     Stats,
     Graphs,
     Network,
+    HallOfFame,
     Settings,
 }
 #[derive(Resource)]
@@ -44,6 +55,30 @@ pub struct SimStats {
     pub best_cell_pos: Vec2,
     pub oldest_cell_pos: Vec2,
     pub sim_start_ts: InstantTracker,
+    /// Population means of the brains' own mutation parameters
+    pub mean_mutation_rate: f32,
+    pub mean_mutation_variation: f32,
+}
+
+/// How the population is evolving, grouped to keep the side panel system's parameter count down
+#[derive(SystemParam)]
+struct PopulationInfo<'w> {
+    species_map: Res<'w, SpeciesMap>,
+    es_trainer: Res<'w, EsTrainer>,
+    cell_id: Res<'w, CellId>,
+    lineage: Res<'w, Lineage>,
+}
+
+/// Files and cells the panel can save or spawn, grouped for the same reason
+#[derive(SystemParam)]
+struct PanelActions<'w> {
+    export_writer: EventWriter<'w, ExportFocusedBrainEvent>,
+    lineage_writer: EventWriter<'w, ExportLineageEvent>,
+    clone_writer: EventWriter<'w, CloneDemoEvent>,
+    inject_writer: EventWriter<'w, InjectFameEntryEvent>,
+    reset_writer: EventWriter<'w, ResetRunEvent>,
+    recorder: ResMut<'w, DemoRecorder>,
+    hall_of_fame: ResMut<'w, HallOfFame>,
 }
 
 #[derive(Resource, Default)]
@@ -51,6 +86,9 @@ struct GraphPoints {
     score: Vec<f32>,
     age: Vec<f32>,
     num_cells: Vec<f32>,
+    mutation_variation: Vec<f32>,
+    /// `(species id, members)` of every species at each point
+    species_sizes: Vec<Vec<(u32, usize)>>,
 }
 
 impl Plugin for GuiPlugin {
@@ -62,11 +100,9 @@ impl Plugin for GuiPlugin {
             .add_systems(Startup, setup)
             .add_systems(Update, update_stats)
             .add_systems(Update, handle_mouse_btn_click)
-            .add_systems(
-                Update,
-                update_graph_points.run_if(on_timer(Duration::from_secs_f32(1.0))),
-            )
-            .add_systems(Update, update_side_panel);
+            .add_systems(Update, update_graph_points.run_if(on_sim_timer(1.0)))
+            .add_systems(Update, update_side_panel)
+            .add_systems(PreUpdate, clear_stats.run_if(on_event::<ResetRunEvent>()));
     }
 }
 
@@ -83,8 +119,11 @@ fn update_side_panel(
     focused_cell_stats: Res<FocusedCellStats>,
     mut settings: ResMut<SimSettings>,
     mut dynamic_settings: ResMut<DynamicSettings>,
+    mut sim_rng: ResMut<SimRng>,
+    population: PopulationInfo,
     food_tree: Res<FoodTree>,
     best_brain: Res<FocusedCellNet>,
+    mut actions: PanelActions,
     cells_query: Query<(&Cell, &Transform), With<Cell>>,
     food_query: Query<With<Food>>,
     bullet_query: Query<With<Bullet>>,
@@ -94,6 +133,8 @@ fn update_side_panel(
     }
 
     let ctx = contexts.ctx_mut();
+    let species_map = &population.species_map;
+    let es_trainer = &population.es_trainer;
     let tree_size = match &food_tree.0 {
         Some(v) => v.len(),
         None => 0,
@@ -107,6 +148,11 @@ fn update_side_panel(
                 ui.selectable_value(&mut panel.0, Panel::Stats, Panel::Stats.get_label());
                 ui.selectable_value(&mut panel.0, Panel::Graphs, Panel::Graphs.get_label());
                 ui.selectable_value(&mut panel.0, Panel::Network, Panel::Network.get_label());
+                ui.selectable_value(
+                    &mut panel.0,
+                    Panel::HallOfFame,
+                    Panel::HallOfFame.get_label(),
+                );
                 ui.selectable_value(&mut panel.0, Panel::Settings, Panel::Settings.get_label());
             });
             ui.separator();
@@ -121,6 +167,52 @@ fn update_side_panel(
                             ui.label(format!("Bullets: {:?}", bullet_query.iter().len()));
                             ui.label(format!("Max Fitness: {:?}", stats.max_score));
                             ui.label(format!("Max Lifespan: {:.2}", stats.max_age));
+                            ui.label(format!(
+                                "Mutation: rate {:.3}, variation {:.3}",
+                                stats.mean_mutation_rate, stats.mean_mutation_variation
+                            ));
+                        });
+                    egui::CollapsingHeader::new("Trainer")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            ui.label(format!("Mode: {}", dynamic_settings.trainer.get_label()));
+                            ui.label(format!("Cells evaluated: {}", population.cell_id.0));
+                            if es_trainer.is_running() {
+                                ui.label(format!(
+                                    "Generation: {} ({:.0}s left)",
+                                    es_trainer.generation(),
+                                    es_trainer.time_left()
+                                ));
+                            }
+                            if let Some((best, mean)) = es_trainer.history.last() {
+                                ui.label(format!(
+                                    "Last ES fitness: best {:.1}, mean {:.1}",
+                                    best, mean
+                                ));
+                            }
+                            if let Some(err) = &es_trainer.last_error {
+                                ui.colored_label(
+                                    Color32::RED,
+                                    format!("Skipped generation: {}", err),
+                                );
+                            }
+                        });
+                    egui::CollapsingHeader::new("Species")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            ui.label(format!(
+                                "Species: {} (threshold {:.2})",
+                                species_map.species.len(),
+                                species_map.threshold
+                            ));
+                            let mut species: Vec<_> = species_map.species.iter().collect();
+                            species.sort_by(|a, b| b.num_members.cmp(&a.num_members));
+                            for s in species.iter().take(MAX_LISTED_SPECIES) {
+                                ui.colored_label(
+                                    to_color32(s.color),
+                                    format!("#{}: {} cells", s.id, s.num_members),
+                                );
+                            }
                         });
                     egui::CollapsingHeader::new("Cell")
                         .default_open(true)
@@ -144,8 +236,43 @@ fn update_side_panel(
                                     "Fitness: {:?}",
                                     focused_cell_stats.fitness_score
                                 ));
+                                for (label, value) in focused_cell_stats.body_inputs.iter() {
+                                    ui.label(format!("Input {}: {:.2}", label, value));
+                                }
+                                let lineage = &population.lineage;
+                                if let Some(record) = lineage.get(focused_cell_stats.id) {
+                                    ui.label(format!(
+                                        "Generation: {}, founder #{}",
+                                        record.generation,
+                                        lineage.founder(record.id)
+                                    ));
+                                    if let Some(parent) = record.parent {
+                                        ui.label(format!("Parent: #{}", parent));
+                                    }
+                                }
                             }
                         });
+                    egui::CollapsingHeader::new("Lineage")
+                        .default_open(false)
+                        .show(ui, |ui| {
+                            ui.label(format!("Cells recorded: {}", population.lineage.len()));
+                            ui.label(format!(
+                                "Max generation: {}",
+                                population.lineage.max_generation()
+                            ));
+                            ui.horizontal(|ui| {
+                                if ui.button("Export DOT").clicked() {
+                                    actions
+                                        .lineage_writer
+                                        .send(ExportLineageEvent(LineageFormat::Dot));
+                                }
+                                if ui.button("Export JSON").clicked() {
+                                    actions
+                                        .lineage_writer
+                                        .send(ExportLineageEvent(LineageFormat::Json));
+                                }
+                            });
+                        });
                     egui::CollapsingHeader::new("Debug")
                         .default_open(false)
                         .show(ui, |ui| {
@@ -173,6 +300,33 @@ fn update_side_panel(
                             .map(|i| [i as f64, graph_points.num_cells[i] as f64])
                             .collect::<PlotPoints>(),
                     );
+                    let line4 = Line::new(
+                        (0..graph_points.mutation_variation.len())
+                            .map(|i| [i as f64, graph_points.mutation_variation[i] as f64])
+                            .collect::<PlotPoints>(),
+                    );
+                    let mut species_lines: Vec<(u32, Vec<[f64; 2]>)> = Vec::new();
+                    for (i, sizes) in graph_points.species_sizes.iter().enumerate() {
+                        for (id, num_members) in sizes.iter() {
+                            let point = [i as f64, *num_members as f64];
+                            match species_lines.iter_mut().find(|(l, _)| l == id) {
+                                Some((_, points)) => points.push(point),
+                                None => species_lines.push((*id, vec![point])),
+                            }
+                        }
+                    }
+                    let es_best = Line::new(
+                        (0..es_trainer.history.len())
+                            .map(|i| [i as f64, es_trainer.history[i].0 as f64])
+                            .collect::<PlotPoints>(),
+                    )
+                    .name("best");
+                    let es_mean = Line::new(
+                        (0..es_trainer.history.len())
+                            .map(|i| [i as f64, es_trainer.history[i].1 as f64])
+                            .collect::<PlotPoints>(),
+                    )
+                    .name("mean");
                     let aspect = 1.8;
                     egui::CollapsingHeader::new("Score")
                         .default_open(true)
@@ -195,16 +349,138 @@ fn update_side_panel(
                                 .view_aspect(aspect)
                                 .show(ui, |plot_ui| plot_ui.line(line3));
                         });
+                    egui::CollapsingHeader::new("Mutation Variation")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            Plot::new("mutation")
+                                .view_aspect(aspect)
+                                .show(ui, |plot_ui| plot_ui.line(line4));
+                        });
+                    if !es_trainer.history.is_empty() {
+                        egui::CollapsingHeader::new("ES Fitness")
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                Plot::new("es").view_aspect(aspect).show(ui, |plot_ui| {
+                                    plot_ui.line(es_best);
+                                    plot_ui.line(es_mean);
+                                });
+                            });
+                    }
+                    egui::CollapsingHeader::new("Species Sizes")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            Plot::new("species")
+                                .view_aspect(aspect)
+                                .show(ui, |plot_ui| {
+                                    for (id, points) in species_lines {
+                                        plot_ui.line(
+                                            Line::new(PlotPoints::new(points))
+                                                .color(to_color32(species_color(id))),
+                                        );
+                                    }
+                                });
+                        });
                 }
                 Panel::Network => {
-                    let shapes = get_nn_shapes(&best_brain);
+                    let rect = ui.available_rect_before_wrap();
+                    let shapes = ui.fonts(|fonts| get_nn_shapes(&best_brain, rect, fonts));
                     if shapes.is_empty() {
                         ui.label("Select a cell first");
+                        return;
+                    }
+                    ui.painter().extend(shapes);
+
+                    // Buttons go below the painted network
+                    ui.add_space(NN_VIZ_HEIGHT);
+                    match &best_brain.brain {
+                        Some(Brain::Dense(net)) => {
+                            let activations = net.activations();
+                            let activations: Vec<&str> =
+                                activations.iter().map(|a| a.get_label()).collect();
+                            ui.label(format!("Activations: {}", activations.join(", ")));
+                            let kinds = net.layer_kinds();
+                            let kinds: Vec<&str> = kinds.iter().map(|k| k.get_label()).collect();
+                            ui.label(format!("Layers: {}", kinds.join(", ")));
+                        }
+                        Some(Brain::Neat(genome)) => {
+                            let num_enabled =
+                                genome.connections().iter().filter(|c| c.enabled).count();
+                            ui.label(format!(
+                                "NEAT: {} nodes, {}/{} connections",
+                                genome.nodes().len(),
+                                num_enabled,
+                                genome.connections().len()
+                            ));
+                        }
+                        None => {}
+                    }
+                    if let Some(brain) = &best_brain.brain {
+                        let mutation = brain.mutation();
+                        ui.label(format!(
+                            "Mutation: rate {:.3}, variation {:.3}",
+                            mutation.rate, mutation.variation
+                        ));
+                    }
+                    for (i, memory) in best_brain.memory.iter().enumerate() {
+                        if memory.is_empty() {
+                            continue;
+                        }
+                        let values: Vec<String> =
+                            memory.iter().map(|v| format!("{:.2}", v)).collect();
+                        ui.label(format!("Memory {}: {}", i + 1, values.join(" ")));
                     }
-                    shapes.iter().for_each(|s| {
-                        ui.painter().add(s.clone());
+                    ui.horizontal(|ui| {
+                        if ui.button("Export brain").clicked() {
+                            actions
+                                .export_writer
+                                .send(ExportFocusedBrainEvent(NetFormat::Json));
+                        }
+                        if ui.button("Export binary").clicked() {
+                            actions
+                                .export_writer
+                                .send(ExportFocusedBrainEvent(NetFormat::Binary));
+                        }
                     });
                 }
+                Panel::HallOfFame => {
+                    let hall_of_fame = &mut actions.hall_of_fame;
+                    ui.label(format!("Brains: {}", hall_of_fame.len()));
+                    ui.label("Sort by");
+                    egui::ComboBox::from_id_source("fame-sort")
+                        .selected_text(hall_of_fame.sort_by.get_label())
+                        .show_ui(ui, |ui| {
+                            for criterion in FameCriterion::ALL {
+                                ui.selectable_value(
+                                    &mut hall_of_fame.sort_by,
+                                    criterion,
+                                    criterion.get_label(),
+                                );
+                            }
+                        });
+                    ui.separator();
+
+                    let mut inject = None;
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        for entry in hall_of_fame.sorted() {
+                            let info = &entry.info;
+                            ui.label(format!(
+                                "Cell {} (gen {}, seed {})",
+                                info.cell_id, info.generation, info.seed
+                            ));
+                            ui.label(format!(
+                                "Energy {:.0}, age {:.0}s, offspring {}",
+                                info.peak_energy, info.lifespan, info.offspring
+                            ));
+                            if ui.button("Inject").clicked() {
+                                inject = Some(info.id);
+                            }
+                            ui.separator();
+                        }
+                    });
+                    if let Some(id) = inject {
+                        actions.inject_writer.send(InjectFameEntryEvent(id));
+                    }
+                }
                 Panel::Settings => {
                     egui::CollapsingHeader::new("Camera")
                         .default_open(true)
@@ -238,6 +514,92 @@ fn update_side_panel(
                             ui.label("Num food");
                             ui.add(egui::DragValue::new(&mut dynamic_settings.num_food).speed(1.0));
                         });
+                    egui::CollapsingHeader::new("Evolution")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            ui.label("Crossover");
+                            egui::ComboBox::from_id_source("crossover")
+                                .selected_text(dynamic_settings.crossover.get_label())
+                                .show_ui(ui, |ui| {
+                                    for mode in Crossover::ALL {
+                                        ui.selectable_value(
+                                            &mut dynamic_settings.crossover,
+                                            mode,
+                                            mode.get_label(),
+                                        );
+                                    }
+                                });
+                            ui.label("Trainer");
+                            egui::ComboBox::from_id_source("trainer")
+                                .selected_text(dynamic_settings.trainer.get_label())
+                                .show_ui(ui, |ui| {
+                                    for mode in TrainerMode::ALL {
+                                        ui.selectable_value(
+                                            &mut dynamic_settings.trainer,
+                                            mode,
+                                            mode.get_label(),
+                                        );
+                                    }
+                                });
+                            ui.label("Actions");
+                            egui::ComboBox::from_id_source("action_mode")
+                                .selected_text(dynamic_settings.action_mode.get_label())
+                                .show_ui(ui, |ui| {
+                                    for mode in ActionMode::ALL {
+                                        ui.selectable_value(
+                                            &mut dynamic_settings.action_mode,
+                                            mode,
+                                            mode.get_label(),
+                                        );
+                                    }
+                                });
+                            ui.checkbox(&mut dynamic_settings.fitness_sharing, "Fitness sharing")
+                                .on_hover_text("Divide a cell's energy between its species");
+                            ui.label(format!("Seed {}", sim_rng.seed()));
+                            ui.add(egui::DragValue::new(&mut sim_rng.next_seed).speed(1.0))
+                                .on_hover_text(
+                                    "Seed of the next run, the current one keeps its own",
+                                );
+                            if ui
+                                .button("Reset")
+                                .on_hover_text("Start the run over from this seed")
+                                .clicked()
+                            {
+                                actions.reset_writer.send(ResetRunEvent);
+                            }
+                            if ui.button("Copy launch command").clicked() {
+                                let command = format!("cargo run -- --seed {}", sim_rng.next_seed);
+                                ui.output_mut(|o| o.copied_text = command);
+                            }
+                        });
+                    egui::CollapsingHeader::new("Demonstrations")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            let recorder = &mut actions.recorder;
+                            let label = if recorder.is_recording() {
+                                "Stop recording"
+                            } else {
+                                "Record user cell"
+                            };
+                            if ui.button(label).on_hover_text("R").clicked() {
+                                recorder.toggle();
+                            }
+                            if recorder.is_recording() {
+                                ui.label(format!("Samples: {}", recorder.demo.samples.len()));
+                            }
+
+                            let Some(path) = recorder.last_saved.clone() else {
+                                return;
+                            };
+                            ui.label(format!("Last: {}", path.display()));
+                            if ui
+                                .button("Clone into population")
+                                .on_hover_text("Train a brain on the last recording")
+                                .clicked()
+                            {
+                                actions.clone_writer.send(CloneDemoEvent(path));
+                            }
+                        });
                 }
             }
         });
@@ -245,12 +607,21 @@ fn update_side_panel(
 
 fn update_graph_points(
     stats: Res<SimStats>,
+    species_map: Res<SpeciesMap>,
     mut graph_points: ResMut<GraphPoints>,
     cells_query: Query<With<Cell>>,
 ) {
     graph_points.add_age(stats.max_age);
     graph_points.add_score(stats.max_score);
     graph_points.add_num_cells(cells_query.iter().count() as f32);
+    graph_points.add_mutation_variation(stats.mean_mutation_variation);
+    graph_points.add_species_sizes(
+        species_map
+            .species
+            .iter()
+            .map(|s| (s.id, s.num_members))
+            .collect(),
+    );
 }
 
 fn handle_mouse_btn_click(
@@ -304,17 +675,27 @@ fn handle_mouse_btn_click(
     }
 }
 
+fn clear_stats(mut stats: ResMut<SimStats>, mut graph_points: ResMut<GraphPoints>) {
+    *stats = SimStats::new();
+    *graph_points = GraphPoints::default();
+}
+
 fn update_stats(
     mut stats: ResMut<SimStats>,
     energy_map: Res<EnergyMap>,
-    cells_query: Query<(&Cell, &BirthTs, &Transform), With<Cell>>,
+    cells_query: Query<(&Cell, &BirthTs, &Transform, &Brain), With<Cell>>,
 ) {
     let mut max_score = 0.0;
     let mut max_age = 0.0;
     let mut best_cell_pos = Vec3::ZERO;
     let mut oldest_cell_pos = Vec3::ZERO;
+    let (mut total_rate, mut total_variation) = (0.0, 0.0);
+
+    for (c, birth_ts, transform, brain) in cells_query.iter() {
+        let mutation = brain.mutation();
+        total_rate += mutation.rate;
+        total_variation += mutation.variation;
 
-    for (c, birth_ts, transform) in cells_query.iter() {
         let score = match energy_map.0.get(&c.0) {
             Some((v, _)) => *v,
             None => 0.0,
@@ -335,127 +716,193 @@ fn update_stats(
     stats.max_age = max_age;
     stats.best_cell_pos = best_cell_pos.truncate();
     stats.oldest_cell_pos = oldest_cell_pos.truncate();
+
+    let num_cells = cells_query.iter().len().max(1) as f64;
+    stats.mean_mutation_rate = (total_rate / num_cells) as f32;
+    stats.mean_mutation_variation = (total_variation / num_cells) as f32;
 }
 
-fn get_nn_shapes(best_brain: &FocusedCellNet) -> Vec<Shape> {
-    if best_brain.0.is_empty() {
+/// Lays the brain out in `rect`, any number of layers of any width
+/// Edges are coloured by the sign of their weight and get thicker with its size
+fn get_nn_shapes(best_brain: &FocusedCellNet, rect: egui::Rect, fonts: &Fonts) -> Vec<Shape> {
+    let Some(brain) = &best_brain.brain else {
+        return Vec::new();
+    };
+    if best_brain.values.is_empty() {
         return Vec::new();
     }
 
     let mut shapes = Vec::new();
-    let tot_height = 450.0;
-
-    // Padding
-    let padding_top = 30.0;
-    // NN viz points
-    let points1 = get_nn_viz_points(NUM_INPUT_NODES as usize, tot_height);
-    let points2 = get_nn_viz_points(NUM_HIDDEN_NODES as usize, tot_height);
-    let points3 = get_nn_viz_points(NUM_OUTPUT_NODES as usize, tot_height);
-    // NN output
-    let values1 = best_brain.0[0].clone();
-    let values2 = best_brain.0[1].clone();
-    let values3 = best_brain.0[2].clone();
-    // x's
-    let x1 = 25.0;
-    let x2 = 100.0;
-    let x3 = 175.0;
-
-    // colors
-    let colors1: Vec<Color32> = values1
-        .iter()
-        .rev()
-        .map(|v| {
-            if *v <= 0.7 {
-                Color32::GREEN
-            } else {
-                Color32::RED
-            }
-        })
+    let layout = brain.layout();
+    let num_layers = layout.layer_sizes.len();
+
+    // Room for the input labels on the left and the output labels on the right
+    let label_width = NN_LABEL_WIDTH;
+    let left = rect.left() + label_width;
+    let layer_spacing = (rect.width() - 2.0 * label_width).max(0.0) / (num_layers - 1) as f32;
+    let xs: Vec<f32> = (0..num_layers)
+        .map(|l| left + l as f32 * layer_spacing)
         .collect();
-    let colors2: Vec<Color32> = values2
+    let points: Vec<Vec<f32>> = layout
+        .layer_sizes
         .iter()
-        .rev()
-        .map(|v| {
-            if *v > 0.5 {
-                Color32::GREEN
-            } else {
-                Color32::RED
-            }
+        .map(|n| {
+            get_nn_viz_points(*n, NN_VIZ_HEIGHT)
+                .into_iter()
+                .map(|y| y + rect.top())
+                .collect()
         })
         .collect();
-    let mut colors3 = vec![Color32::RED, Color32::RED, Color32::RED, Color32::RED];
-    colors3[0] = if values3[0] >= values3[1] {
+    let widest = layout.layer_sizes.iter().max().copied().unwrap_or(1);
+    let node_size = NN_NODE_SIZE.min(NN_VIZ_HEIGHT / (widest + 1) as f32 * 0.4);
+
+    // colors
+    // Hidden and output values are normalized so the thresholds hold for any activation
+    let mut colors: Vec<Vec<Color32>> = Vec::new();
+    for (l, values) in best_brain.values.iter().enumerate() {
+        if l == 0 {
+            colors.push(
+                values
+                    .iter()
+                    .map(|v| {
+                        if *v <= 0.7 {
+                            Color32::GREEN
+                        } else {
+                            Color32::RED
+                        }
+                    })
+                    .collect(),
+            );
+            continue;
+        }
+
+        let values: Vec<f64> = values
+            .iter()
+            .zip(layout.activations[l - 1].iter())
+            .map(|(v, a)| a.normalize(*v))
+            .collect();
+        if l == num_layers - 1 {
+            colors.push(get_output_colors(&values));
+        } else {
+            colors.push(
+                values
+                    .iter()
+                    .map(|v| {
+                        if *v > 0.5 {
+                            Color32::GREEN
+                        } else {
+                            Color32::RED
+                        }
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    // lines
+    let max_weight = layout
+        .edges
+        .iter()
+        .map(|e| e.weight.abs())
+        .fold(f64::EPSILON, f64::max);
+    for edge in layout.edges.iter() {
+        let (l1, n1) = edge.from;
+        let (l2, n2) = edge.to;
+        let strength = (edge.weight.abs() / max_weight) as f32;
+        let (r, g, b) = if edge.weight >= 0.0 {
+            NN_POSITIVE_WEIGHT_COLOR
+        } else {
+            NN_NEGATIVE_WEIGHT_COLOR
+        };
+        let color = Color32::from_rgb(r, g, b);
+        shapes.push(egui::Shape::line(
+            vec![pos2(xs[l1], points[l1][n1]), pos2(xs[l2], points[l2][n2])],
+            Stroke {
+                width: 0.3 + 2.7 * strength,
+                color: color.gamma_multiply(0.25 + 0.75 * strength),
+            },
+        ));
+    }
+
+    // nodes
+    for l in 0..num_layers {
+        for (p, c) in points[l].iter().zip(colors[l].iter()) {
+            shapes.push(get_nn_node_shape(xs[l], *p, node_size, *c));
+        }
+    }
+
+    // labels
+    let font = FontId::proportional(9.0);
+    let input_labels = input_labels();
+    let input_labels: Vec<&str> = input_labels.iter().map(|l| l.as_str()).collect();
+    let output_labels = output_labels();
+    let output_labels: Vec<&str> = output_labels.iter().map(|l| l.as_str()).collect();
+    let labeled_layers = [
+        (0, &input_labels[..], Align2::RIGHT_CENTER, -1.0),
+        (num_layers - 1, &output_labels[..], Align2::LEFT_CENTER, 1.0),
+    ];
+    for (l, labels, anchor, side) in labeled_layers {
+        if points[l].len() > NN_MAX_LABELED_NODES {
+            continue;
+        }
+        for (p, label) in points[l].iter().zip(labels.iter()) {
+            let pos = pos2(xs[l] + side * (node_size + 3.0), *p);
+            shapes.push(Shape::text(
+                fonts,
+                pos,
+                anchor,
+                label,
+                font.clone(),
+                Color32::LIGHT_GRAY,
+            ));
+        }
+    }
+
+    shapes
+}
+
+fn get_output_colors(values: &[f64]) -> Vec<Color32> {
+    let mut colors = vec![Color32::RED; values.len()];
+    if values.len() != NUM_OUTPUT_NODES {
+        return colors;
+    }
+
+    colors[0] = if values[0] >= values[1] {
         Color32::GREEN
     } else {
         Color32::RED
     };
-    colors3[1] = if values3[1] >= values3[0] {
+    colors[1] = if values[1] >= values[0] {
         Color32::GREEN
     } else {
         Color32::RED
     };
-    colors3[2] = if values3[2] >= 0.7 {
+    colors[2] = if values[2] >= 0.7 {
         Color32::GREEN
     } else {
         Color32::RED
     };
-    colors3[3] = if values3[3] >= 0.7 {
+    colors[3] = if values[3] >= 0.7 {
         Color32::GREEN
     } else {
         Color32::RED
     };
-
-    // layer 1 -> 2 lines
-    for (p1, c1) in points1.iter().zip(colors1.iter()) {
-        for (p2, c2) in points2.iter().zip(colors2.iter()) {
-            let mut color = Color32::RED;
-            if are_colors_equal(*c1, *c2) {
-                color = Color32::GREEN;
-            }
-            shapes.push(egui::Shape::line(
-                vec![pos2(x1, *p1 + padding_top), pos2(x2, *p2 + padding_top)],
-                Stroke { width: 1.0, color },
-            ));
-        }
-    }
-
-    // layer 2 -> 3 lines
-    for (p2, c2) in points2.iter().zip(colors2.iter()) {
-        for (p3, c3) in points3.iter().zip(colors3.iter()) {
-            let mut color = Color32::RED;
-            if are_colors_equal(*c2, *c3) {
-                color = Color32::GREEN;
-            }
-            shapes.push(egui::Shape::line(
-                vec![pos2(x2, *p2 + padding_top), pos2(x3, *p3 + padding_top)],
-                Stroke { width: 1.0, color },
-            ));
-        }
-    }
-
-    // layer 1
-    let padding = padding_top;
-    for (p, c) in points1.iter().zip(colors1.iter()) {
-        shapes.push(get_nn_node_shape(x1, *p + padding, *c));
-    }
-    // layer 2
-    let padding = padding_top;
-    for (p, c) in points2.iter().zip(colors2.iter()) {
-        shapes.push(get_nn_node_shape(x2, *p + padding, *c));
-    }
-    // layer 3
-    let padding = padding_top;
-    for (p, c) in points3.iter().zip(colors3.iter()) {
-        shapes.push(get_nn_node_shape(x3, *p + padding, *c));
+    for (i, extra) in EXTRA_ACTIONS.iter().enumerate() {
+        let value = values[NUM_BASE_OUTPUT_NODES + i];
+        let active = match extra {
+            ExtraAction::Strafe => !(0.3..0.7).contains(&value),
+            _ => value >= 0.7,
+        };
+        colors[NUM_BASE_OUTPUT_NODES + i] = if active { Color32::GREEN } else { Color32::RED };
     }
 
-    shapes
+    colors
 }
 
-fn get_nn_node_shape(x: f32, y: f32, color: Color32) -> egui::Shape {
+fn get_nn_node_shape(x: f32, y: f32, radius: f32, color: Color32) -> egui::Shape {
     egui::Shape::Circle(CircleShape {
         center: (x, y).into(),
-        radius: NN_NODE_SIZE,
+        radius,
         fill: color,
         stroke: Stroke {
             width: 1.0,
@@ -476,8 +923,9 @@ fn get_nn_viz_points(n: usize, tot_size: f32) -> Vec<f32> {
     points
 }
 
-fn are_colors_equal(first: Color32, second: Color32) -> bool {
-    (first.g() == 255 && second.g() == 255) || (first.r() == 255 && second.r() == 255)
+fn to_color32(color: Color) -> Color32 {
+    let [r, g, b, _] = color.as_rgba_u8();
+    Color32::from_rgb(r, g, b)
 }
 
 impl SimStats {
@@ -488,6 +936,8 @@ impl SimStats {
             max_score: 0.0,
             oldest_cell_pos: Vec2::ZERO,
             sim_start_ts: InstantTracker::default(),
+            mean_mutation_rate: 0.0,
+            mean_mutation_variation: 0.0,
         }
     }
 }
@@ -498,6 +948,7 @@ impl Panel {
             Panel::Stats => "Stats",
             Panel::Graphs => "Graphs",
             Panel::Network => "Network",
+            Panel::HallOfFame => "Fame",
             Panel::Settings => "Settings",
         }
     }
@@ -524,4 +975,18 @@ impl GraphPoints {
             self.num_cells.remove(0);
         }
     }
+
+    pub fn add_mutation_variation(&mut self, value: f32) {
+        self.mutation_variation.push(value);
+        if self.mutation_variation.len() > MAX_GRAPH_POINTS {
+            self.mutation_variation.remove(0);
+        }
+    }
+
+    pub fn add_species_sizes(&mut self, value: Vec<(u32, usize)>) {
+        self.species_sizes.push(value);
+        if self.species_sizes.len() > MAX_GRAPH_POINTS {
+            self.species_sizes.remove(0);
+        }
+    }
 }
@@ -2,10 +2,13 @@ pub mod bullet;
 pub mod camera;
 pub mod cell;
 pub mod configs;
+pub mod es;
 pub mod food;
 pub mod gui;
+pub mod neat;
 pub mod nn;
 pub mod physics;
+pub mod rng;
 pub mod settings;
 pub mod trackers;
 
@@ -1,6 +1,7 @@
 use ava::{
     bullet::BulletPlugin, camera::FollowCameraPlugin, cell::CellPlugin, food::FoodPlugin,
-    gui::GuiPlugin, physics::PhysicsPlugin, settings::SettingsPlugin, trackers::TrackersPlugin, *,
+    gui::GuiPlugin, physics::PhysicsPlugin, rng::SimRngPlugin, settings::SettingsPlugin,
+    trackers::TrackersPlugin, *,
 };
 use bevy::{
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
@@ -34,6 +35,7 @@ fn main() {
         )))
         .add_plugins(GuiPlugin)
         .add_plugins(SettingsPlugin)
+        .add_plugins(SimRngPlugin)
         .add_plugins(TrackersPlugin)
         .add_plugins(PhysicsPlugin)
         .add_plugins(FollowCameraPlugin)
@@ -0,0 +1,1005 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    nn::{invalid_data, Activation, ByteReader, IoLabels, IoMapping, MutationParams, NetError},
+    *,
+};
+
+/// `GenomeFile` layout written by this build, 2 added the mutation parameters, 3 the labels
+pub const GENOME_FILE_VERSION: u32 = 3;
+pub(crate) const GENOME_BIN_MAGIC: &[u8; 4] = b"AVAG";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum NodeKind {
+    Input,
+    Hidden,
+    Output,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeGene {
+    pub id: usize,
+    pub kind: NodeKind,
+    pub bias: f64,
+    pub activation: Activation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionGene {
+    pub innovation: usize,
+    pub from: usize,
+    pub to: usize,
+    pub weight: f64,
+    pub enabled: bool,
+}
+
+/// NEAT style genome, a feed-forward graph that grows hidden nodes and connections
+/// Inputs are nodes `0..n_inputs`, outputs follow them, hidden nodes come after
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Genome {
+    n_inputs: usize,
+    n_outputs: usize,
+    hidden_activation: Activation,
+    nodes: Vec<NodeGene>,
+    connections: Vec<ConnectionGene>,
+    /// Absent before `GENOME_FILE_VERSION` 2, such genomes start from the defaults
+    #[serde(default)]
+    mutation: MutationParams,
+}
+
+/// Historical markings shared by the whole population
+/// The same structural change always gets the same number, so genomes can be lined up during crossover
+#[derive(Default)]
+pub struct Innovations {
+    connections: HashMap<(usize, usize), usize>,
+    splits: HashMap<usize, usize>,
+    next_innovation: usize,
+    next_node: usize,
+}
+
+/// `Genome` with its nodes sorted once in evaluation order and connections grouped per node
+/// Gives the same outputs as `Genome::predict`, without redoing the layer sort per prediction
+#[derive(Clone)]
+pub struct FlatGenome {
+    n_inputs: usize,
+    nodes: Vec<FlatNode>,
+    /// `(node index, weight)` of every enabled connection, grouped by target node
+    incoming: Vec<(usize, f64)>,
+    /// Node indices of the outputs, in the order `predict` returns them
+    outputs: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct FlatNode {
+    /// Index into the inputs for input nodes
+    input: Option<usize>,
+    bias: f64,
+    activation: Activation,
+    incoming: std::ops::Range<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GenomeFile {
+    version: u32,
+    genome: Genome,
+    /// Missing before version 3
+    #[serde(default)]
+    labels: IoLabels,
+}
+
+impl Genome {
+    /// Minimal topology, every input connected straight to every output
+    pub fn new(
+        n_inputs: usize,
+        n_outputs: usize,
+        hidden_activation: Activation,
+        output_activation: Activation,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) -> Self {
+        innovations.reserve_nodes(n_inputs + n_outputs);
+
+        let mut nodes = Vec::new();
+        for id in 0..n_inputs {
+            nodes.push(NodeGene {
+                id,
+                kind: NodeKind::Input,
+                bias: 0.0,
+                activation: Activation::Linear,
+            });
+        }
+        for id in n_inputs..n_inputs + n_outputs {
+            nodes.push(NodeGene {
+                id,
+                kind: NodeKind::Output,
+                bias: rng.gen_range(-1.0..1.0),
+                activation: output_activation,
+            });
+        }
+
+        let mut connections = Vec::new();
+        for from in 0..n_inputs {
+            for to in n_inputs..n_inputs + n_outputs {
+                connections.push(ConnectionGene {
+                    innovation: innovations.connection(from, to),
+                    from,
+                    to,
+                    weight: rng.gen_range(-1.0..1.0),
+                    enabled: true,
+                });
+            }
+        }
+
+        Self {
+            n_inputs,
+            n_outputs,
+            hidden_activation,
+            nodes,
+            connections,
+            mutation: MutationParams::default(),
+        }
+    }
+
+    pub fn n_inputs(&self) -> usize {
+        self.n_inputs
+    }
+
+    pub fn n_outputs(&self) -> usize {
+        self.n_outputs
+    }
+
+    pub fn nodes(&self) -> &[NodeGene] {
+        &self.nodes
+    }
+
+    pub fn connections(&self) -> &[ConnectionGene] {
+        &self.connections
+    }
+
+    pub fn mutation(&self) -> MutationParams {
+        self.mutation
+    }
+
+    pub fn output_activation(&self) -> Activation {
+        self.nodes
+            .iter()
+            .find(|n| n.kind == NodeKind::Output)
+            .map(|n| n.activation)
+            .unwrap_or_default()
+    }
+
+    /// Node ids grouped by depth, inputs come first and outputs last
+    /// A node's depth is the longest enabled path leading to it
+    pub fn layers(&self) -> Vec<Vec<usize>> {
+        let mut incoming: HashMap<usize, Vec<usize>> = HashMap::new();
+        for c in self.connections.iter().filter(|c| c.enabled) {
+            incoming.entry(c.to).or_default().push(c.from);
+        }
+
+        let mut depths: HashMap<usize, usize> = HashMap::new();
+        for node in self.nodes.iter() {
+            self.depth(node.id, &incoming, &mut depths);
+        }
+
+        let hidden_depth = self
+            .nodes
+            .iter()
+            .filter(|n| n.kind == NodeKind::Hidden)
+            .map(|n| depths[&n.id])
+            .max()
+            .unwrap_or(0);
+        let output_depth = hidden_depth + 1;
+
+        let mut layers = vec![Vec::new(); output_depth + 1];
+        for node in self.nodes.iter() {
+            let depth = match node.kind {
+                NodeKind::Input => 0,
+                NodeKind::Hidden => depths[&node.id],
+                NodeKind::Output => output_depth,
+            };
+            layers[depth].push(node.id);
+        }
+        layers.iter_mut().for_each(|l| l.sort());
+        layers.retain(|l| !l.is_empty());
+
+        layers
+    }
+
+    /// Node values grouped the same way as `layers`
+    pub fn predict(&self, inputs: &[f64]) -> Result<Vec<Vec<f64>>, NetError> {
+        NetError::check_input_size(self.n_inputs, inputs)?;
+
+        let layers = self.layers();
+        let node_index: HashMap<usize, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.id, i))
+            .collect();
+        let mut incoming: HashMap<usize, Vec<(usize, f64)>> = HashMap::new();
+        for c in self.connections.iter().filter(|c| c.enabled) {
+            incoming.entry(c.to).or_default().push((c.from, c.weight));
+        }
+
+        let mut values: HashMap<usize, f64> = HashMap::new();
+        let mut outputs = Vec::new();
+        for layer in layers.iter() {
+            let mut layer_values = Vec::new();
+            for id in layer.iter() {
+                let node = &self.nodes[node_index[id]];
+                let value = match node.kind {
+                    NodeKind::Input => inputs[node.id],
+                    _ => {
+                        let mut total = node.bias;
+                        for (from, weight) in incoming.get(id).into_iter().flatten() {
+                            total += weight * values.get(from).copied().unwrap_or(0.0);
+                        }
+                        node.activation.apply(total)
+                    }
+                };
+                values.insert(*id, value);
+                layer_values.push(value);
+            }
+            outputs.push(layer_values);
+        }
+
+        Ok(outputs)
+    }
+
+    /// Adapts the mutation parameters, then mutates weights, biases and topology
+    pub fn mutate(&mut self, innovations: &mut Innovations, rng: &mut impl Rng) {
+        self.mutation.adapt(rng);
+        let MutationParams { rate, variation } = self.mutation;
+        for c in self.connections.iter_mut() {
+            if rng.gen_range(0.0..1.0) < rate {
+                c.weight += rng.gen_range(-variation..variation);
+            }
+        }
+        for n in self.nodes.iter_mut().filter(|n| n.kind != NodeKind::Input) {
+            if rng.gen_range(0.0..1.0) < rate {
+                n.bias += rng.gen_range(-variation..variation);
+            }
+        }
+
+        if rng.gen_range(0.0..1.0) < NEAT_ADD_CONNECTION_RATE {
+            self.add_connection(innovations, rng);
+        }
+        if rng.gen_range(0.0..1.0) < NEAT_ADD_NODE_RATE {
+            self.add_node(innovations, rng);
+        }
+        if rng.gen_range(0.0..1.0) < NEAT_TOGGLE_CONNECTION_RATE && !self.connections.is_empty() {
+            let index = rng.gen_range(0..self.connections.len());
+            self.connections[index].enabled = !self.connections[index].enabled;
+        }
+    }
+
+    /// NEAT compatibility distance, based on the genes the genomes don't share
+    /// and on the weight difference of the ones they do
+    pub fn distance(&self, other: &Genome) -> f64 {
+        if self.n_inputs != other.n_inputs || self.n_outputs != other.n_outputs {
+            return f64::INFINITY;
+        }
+
+        let other_genes: HashMap<usize, &ConnectionGene> = other
+            .connections
+            .iter()
+            .map(|c| (c.innovation, c))
+            .collect();
+        let mut num_matching = 0;
+        let mut weight_diff = 0.0;
+        for gene in self.connections.iter() {
+            if let Some(other_gene) = other_genes.get(&gene.innovation) {
+                num_matching += 1;
+                weight_diff += (gene.weight - other_gene.weight).abs();
+            }
+        }
+
+        let num_genes = self.connections.len().max(other.connections.len()).max(1);
+        let num_unmatched = self.connections.len() + other.connections.len() - 2 * num_matching;
+        let mean_weight_diff = if num_matching > 0 {
+            weight_diff / num_matching as f64
+        } else {
+            0.0
+        };
+
+        NEAT_DISJOINT_COEFFICIENT * num_unmatched as f64 / num_genes as f64
+            + NEAT_WEIGHT_COEFFICIENT * mean_weight_diff
+    }
+
+    /// Genes are lined up by innovation number, matching genes come from either parent
+    /// Disjoint and excess genes come from `self`, which is expected to be the fitter parent
+    pub fn crossover(&self, other: &Genome, rng: &mut impl Rng) -> Genome {
+        let mut child = self.clone();
+        if self.n_inputs != other.n_inputs || self.n_outputs != other.n_outputs {
+            return child;
+        }
+        child.mutation = self.mutation.blend(&other.mutation);
+
+        let other_genes: HashMap<usize, &ConnectionGene> = other
+            .connections
+            .iter()
+            .map(|c| (c.innovation, c))
+            .collect();
+        for gene in child.connections.iter_mut() {
+            let Some(other_gene) = other_genes.get(&gene.innovation) else {
+                continue;
+            };
+            if gene.from != other_gene.from || gene.to != other_gene.to {
+                continue;
+            }
+
+            let disabled = !gene.enabled || !other_gene.enabled;
+            if rng.gen_bool(0.5) {
+                gene.weight = other_gene.weight;
+            }
+            gene.enabled = !(disabled && rng.gen_range(0.0..1.0) < 0.75);
+        }
+
+        let other_nodes: HashMap<usize, &NodeGene> =
+            other.nodes.iter().map(|n| (n.id, n)).collect();
+        for node in child.nodes.iter_mut() {
+            if let Some(other_node) = other_nodes.get(&node.id) {
+                if rng.gen_bool(0.5) {
+                    node.bias = other_node.bias;
+                }
+            }
+        }
+
+        child
+    }
+
+    pub fn to_json(&self, labels: &IoLabels) -> io::Result<String> {
+        let file = GenomeFile {
+            version: GENOME_FILE_VERSION,
+            genome: self.clone(),
+            labels: labels.clone(),
+        };
+        serde_json::to_string_pretty(&file).map_err(invalid_data)
+    }
+
+    /// The labels are empty for files from before version 3
+    pub fn from_json(text: &str) -> io::Result<(Self, IoLabels)> {
+        let file: GenomeFile = serde_json::from_str(text).map_err(invalid_data)?;
+        if file.version > GENOME_FILE_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported genome version {}",
+                file.version
+            )));
+        }
+
+        let genome = file.genome.validated()?;
+        file.labels.check(genome.n_inputs, genome.n_outputs)?;
+        Ok((genome, file.labels))
+    }
+
+    /// Compact little endian encoding
+    /// magic, version, n_inputs, n_outputs, hidden activation, mutation rate and variation,
+    /// the input and output labels, then the node genes and the connection genes,
+    /// each prefixed by their count
+    pub fn to_bytes(&self, labels: &IoLabels) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(GENOME_BIN_MAGIC);
+        bytes.extend_from_slice(&GENOME_FILE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.n_inputs as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.n_outputs as u32).to_le_bytes());
+        bytes.push(self.hidden_activation.to_byte());
+        bytes.extend_from_slice(&self.mutation.rate.to_le_bytes());
+        bytes.extend_from_slice(&self.mutation.variation.to_le_bytes());
+        labels.write(&mut bytes);
+
+        bytes.extend_from_slice(&(self.nodes.len() as u32).to_le_bytes());
+        for n in self.nodes.iter() {
+            bytes.extend_from_slice(&(n.id as u32).to_le_bytes());
+            bytes.push(n.kind.to_byte());
+            bytes.push(n.activation.to_byte());
+            bytes.extend_from_slice(&n.bias.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.connections.len() as u32).to_le_bytes());
+        for c in self.connections.iter() {
+            bytes.extend_from_slice(&(c.innovation as u32).to_le_bytes());
+            bytes.extend_from_slice(&(c.from as u32).to_le_bytes());
+            bytes.extend_from_slice(&(c.to as u32).to_le_bytes());
+            bytes.extend_from_slice(&c.weight.to_le_bytes());
+            bytes.push(c.enabled as u8);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(Self, IoLabels)> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.take(GENOME_BIN_MAGIC.len())? != GENOME_BIN_MAGIC {
+            return Err(invalid_data("Not a genome file"));
+        }
+
+        let version = reader.read_u32()?;
+        if version > GENOME_FILE_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported genome version {}",
+                version
+            )));
+        }
+        let n_inputs = reader.read_u32()? as usize;
+        let n_outputs = reader.read_u32()? as usize;
+        let hidden_activation = Activation::from_byte(reader.read_u8()?)?;
+        let mut mutation = MutationParams::default();
+        if version >= 2 {
+            mutation.rate = reader.read_f64()?;
+            mutation.variation = reader.read_f64()?;
+        }
+        let labels = match version >= 3 {
+            true => IoLabels::read(&mut reader)?,
+            false => IoLabels::default(),
+        };
+
+        let mut nodes = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            nodes.push(NodeGene {
+                id: reader.read_u32()? as usize,
+                kind: NodeKind::from_byte(reader.read_u8()?)?,
+                activation: Activation::from_byte(reader.read_u8()?)?,
+                bias: reader.read_f64()?,
+            });
+        }
+
+        let mut connections = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            connections.push(ConnectionGene {
+                innovation: reader.read_u32()? as usize,
+                from: reader.read_u32()? as usize,
+                to: reader.read_u32()? as usize,
+                weight: reader.read_f64()?,
+                enabled: reader.read_u8()? != 0,
+            });
+        }
+
+        let genome = Self {
+            n_inputs,
+            n_outputs,
+            hidden_activation,
+            nodes,
+            connections,
+            mutation,
+        }
+        .validated()?;
+        labels.check(genome.n_inputs, genome.n_outputs)?;
+        Ok((genome, labels))
+    }
+
+    /// Copy wired up to the current inputs and outputs, see `Net::remap_io`
+    /// Hidden nodes are moved past the new inputs and outputs when there are more of them
+    pub fn remap_io(&self, mapping: &IoMapping) -> Genome {
+        let (n_inputs, n_outputs) = (mapping.inputs.len(), mapping.outputs.len());
+        let shift = (n_inputs + n_outputs).saturating_sub(self.n_inputs + self.n_outputs);
+
+        // New id of every saved node, inputs and outputs the layout no longer has are left out
+        let mut ids = HashMap::new();
+        for (id, saved) in mapping.inputs.iter().enumerate() {
+            if let Some(saved) = saved {
+                ids.insert(*saved, id);
+            }
+        }
+        for (i, saved) in mapping.outputs.iter().enumerate() {
+            if let Some(saved) = saved {
+                ids.insert(self.n_inputs + saved, n_inputs + i);
+            }
+        }
+        let hidden = self.nodes.iter().filter(|n| n.kind == NodeKind::Hidden);
+        for node in hidden.clone() {
+            ids.insert(node.id, node.id + shift);
+        }
+
+        let mut nodes: Vec<NodeGene> = (0..n_inputs)
+            .map(|id| NodeGene {
+                id,
+                kind: NodeKind::Input,
+                bias: 0.0,
+                activation: Activation::Linear,
+            })
+            .collect();
+        let output_activation = self.output_activation();
+        for (i, saved) in mapping.outputs.iter().enumerate() {
+            let id = n_inputs + i;
+            nodes.push(match saved {
+                Some(saved) => NodeGene {
+                    id,
+                    ..self.nodes[self.n_inputs + saved].clone()
+                },
+                None => NodeGene {
+                    id,
+                    kind: NodeKind::Output,
+                    bias: output_activation.bias_for(mapping.new_output_values[i]),
+                    activation: output_activation,
+                },
+            });
+        }
+        nodes.extend(hidden.map(|n| NodeGene {
+            id: n.id + shift,
+            ..n.clone()
+        }));
+
+        let connections = self
+            .connections
+            .iter()
+            .filter_map(|c| {
+                Some(ConnectionGene {
+                    from: *ids.get(&c.from)?,
+                    to: *ids.get(&c.to)?,
+                    ..c.clone()
+                })
+            })
+            .collect();
+
+        Self {
+            n_inputs,
+            n_outputs,
+            hidden_activation: self.hidden_activation,
+            nodes,
+            connections,
+            mutation: self.mutation,
+        }
+    }
+
+    /// Checks the invariants `predict` relies on
+    fn validated(self) -> io::Result<Self> {
+        self.mutation.check()?;
+        let mut ids = HashSet::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if !ids.insert(node.id) {
+                return Err(invalid_data(format!("Duplicate node {}", node.id)));
+            }
+            let expected = if i < self.n_inputs {
+                NodeKind::Input
+            } else if i < self.n_inputs + self.n_outputs {
+                NodeKind::Output
+            } else {
+                NodeKind::Hidden
+            };
+            if node.kind != expected || (node.kind != NodeKind::Hidden && node.id != i) {
+                return Err(invalid_data("Inputs and outputs must come first, in order"));
+            }
+        }
+        if self.nodes.len() < self.n_inputs + self.n_outputs {
+            return Err(invalid_data("Missing input or output nodes"));
+        }
+
+        let kinds: HashMap<usize, NodeKind> = self.nodes.iter().map(|n| (n.id, n.kind)).collect();
+        for c in self.connections.iter() {
+            match (kinds.get(&c.from), kinds.get(&c.to)) {
+                (Some(from), Some(to)) if *from != NodeKind::Output && *to != NodeKind::Input => {}
+                _ => {
+                    return Err(invalid_data(format!(
+                        "Bad connection {} -> {}",
+                        c.from, c.to
+                    )))
+                }
+            }
+        }
+        for c in self.connections.iter() {
+            if c.from == c.to || self.is_reachable(c.to, c.from) {
+                return Err(invalid_data("Genome has a cycle"));
+            }
+        }
+
+        Ok(self)
+    }
+
+    fn depth(
+        &self,
+        id: usize,
+        incoming: &HashMap<usize, Vec<usize>>,
+        depths: &mut HashMap<usize, usize>,
+    ) -> usize {
+        if let Some(d) = depths.get(&id) {
+            return *d;
+        }
+
+        let depth = match incoming.get(&id) {
+            Some(sources) if id >= self.n_inputs => sources
+                .iter()
+                .map(|s| self.depth(*s, incoming, depths) + 1)
+                .max()
+                .unwrap_or(1),
+            _ if id < self.n_inputs => 0,
+            _ => 1,
+        };
+        depths.insert(id, depth);
+
+        depth
+    }
+
+    /// Whether `to` can be reached from `from`, disabled connections included
+    /// so re-enabling a connection can never close a cycle
+    fn is_reachable(&self, from: usize, to: usize) -> bool {
+        let mut stack = vec![from];
+        let mut seen = HashSet::new();
+        while let Some(id) = stack.pop() {
+            if id == to {
+                return true;
+            }
+            if !seen.insert(id) {
+                continue;
+            }
+            for c in self.connections.iter().filter(|c| c.from == id) {
+                stack.push(c.to);
+            }
+        }
+
+        false
+    }
+
+    fn add_connection(&mut self, innovations: &mut Innovations, rng: &mut impl Rng) {
+        let sources: Vec<usize> = self
+            .nodes
+            .iter()
+            .filter(|n| n.kind != NodeKind::Output)
+            .map(|n| n.id)
+            .collect();
+        let targets: Vec<usize> = self
+            .nodes
+            .iter()
+            .filter(|n| n.kind != NodeKind::Input)
+            .map(|n| n.id)
+            .collect();
+
+        // Random attempts are enough, dense genomes simply won't grow
+        for _ in 0..20 {
+            let from = sources[rng.gen_range(0..sources.len())];
+            let to = targets[rng.gen_range(0..targets.len())];
+            if from == to {
+                continue;
+            }
+            if self
+                .connections
+                .iter()
+                .any(|c| c.from == from && c.to == to)
+            {
+                continue;
+            }
+            if self.is_reachable(to, from) {
+                continue;
+            }
+
+            self.connections.push(ConnectionGene {
+                innovation: innovations.connection(from, to),
+                from,
+                to,
+                weight: rng.gen_range(-1.0..1.0),
+                enabled: true,
+            });
+            return;
+        }
+    }
+
+    /// Splits an enabled connection in two with a new hidden node in between
+    fn add_node(&mut self, innovations: &mut Innovations, rng: &mut impl Rng) {
+        let enabled: Vec<usize> = (0..self.connections.len())
+            .filter(|i| self.connections[*i].enabled)
+            .collect();
+        if enabled.is_empty() {
+            return;
+        }
+
+        let index = enabled[rng.gen_range(0..enabled.len())];
+        let old = self.connections[index].clone();
+        let id = innovations.split_node(old.innovation);
+        if self.nodes.iter().any(|n| n.id == id) {
+            return;
+        }
+
+        self.connections[index].enabled = false;
+        self.nodes.push(NodeGene {
+            id,
+            kind: NodeKind::Hidden,
+            bias: 0.0,
+            activation: self.hidden_activation,
+        });
+        self.connections.push(ConnectionGene {
+            innovation: innovations.connection(old.from, id),
+            from: old.from,
+            to: id,
+            weight: 1.0,
+            enabled: true,
+        });
+        self.connections.push(ConnectionGene {
+            innovation: innovations.connection(id, old.to),
+            from: id,
+            to: old.to,
+            weight: old.weight,
+            enabled: true,
+        });
+    }
+}
+
+impl FlatGenome {
+    pub fn new(genome: &Genome) -> Self {
+        let layers = genome.layers();
+        let order: Vec<usize> = layers.iter().flatten().copied().collect();
+        let index_of: HashMap<usize, usize> =
+            order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let node_of: HashMap<usize, &NodeGene> = genome.nodes.iter().map(|n| (n.id, n)).collect();
+
+        let mut nodes = Vec::new();
+        let mut incoming = Vec::new();
+        for id in order.iter() {
+            let node = node_of[id];
+            let start = incoming.len();
+            if node.kind != NodeKind::Input {
+                // Floating point sums depend on order, keep the one `Genome::predict` uses
+                incoming.extend(
+                    genome
+                        .connections
+                        .iter()
+                        .filter(|c| c.enabled && c.to == *id)
+                        .map(|c| (index_of[&c.from], c.weight)),
+                );
+            }
+            nodes.push(FlatNode {
+                input: (node.kind == NodeKind::Input).then_some(node.id),
+                bias: node.bias,
+                activation: node.activation,
+                incoming: start..incoming.len(),
+            });
+        }
+
+        let outputs = match layers.last() {
+            Some(last) => last.iter().map(|id| index_of[id]).collect(),
+            None => Vec::new(),
+        };
+
+        Self {
+            n_inputs: genome.n_inputs,
+            nodes,
+            incoming,
+            outputs,
+        }
+    }
+
+    /// Writes the output nodes into `outputs`
+    /// `scratch` ends up with one value per node in evaluation order, it only grows once
+    pub fn predict_into(
+        &self,
+        inputs: &[f64],
+        scratch: &mut Vec<f64>,
+        outputs: &mut [f64],
+    ) -> Result<(), NetError> {
+        NetError::check_input_size(self.n_inputs, inputs)?;
+        if outputs.len() != self.outputs.len() {
+            return Err(NetError::OutputSize {
+                expected: self.outputs.len(),
+                found: outputs.len(),
+            });
+        }
+
+        scratch.clear();
+        scratch.resize(self.nodes.len(), 0.0);
+        for (i, node) in self.nodes.iter().enumerate() {
+            scratch[i] = match node.input {
+                Some(input) => inputs[input],
+                None => {
+                    let mut total = node.bias;
+                    for (from, weight) in self.incoming[node.incoming.clone()].iter() {
+                        total += weight * scratch[*from];
+                    }
+                    node.activation.apply(total)
+                }
+            };
+        }
+
+        for (output, index) in outputs.iter_mut().zip(self.outputs.iter()) {
+            *output = scratch[*index];
+        }
+        Ok(())
+    }
+}
+
+impl Innovations {
+    /// Makes sure genomes loaded from disk don't reuse numbers handed out by this run
+    pub fn observe(&mut self, genome: &Genome) {
+        for c in genome.connections.iter() {
+            self.connections
+                .entry((c.from, c.to))
+                .or_insert(c.innovation);
+            self.next_innovation = self.next_innovation.max(c.innovation + 1);
+        }
+        let max_node = genome.nodes.iter().map(|n| n.id + 1).max().unwrap_or(0);
+        self.reserve_nodes(max_node);
+    }
+
+    fn reserve_nodes(&mut self, n: usize) {
+        self.next_node = self.next_node.max(n);
+    }
+
+    fn connection(&mut self, from: usize, to: usize) -> usize {
+        if let Some(innovation) = self.connections.get(&(from, to)) {
+            return *innovation;
+        }
+
+        let innovation = self.next_innovation;
+        self.next_innovation += 1;
+        self.connections.insert((from, to), innovation);
+        innovation
+    }
+
+    fn split_node(&mut self, innovation: usize) -> usize {
+        if let Some(id) = self.splits.get(&innovation) {
+            return *id;
+        }
+
+        let id = self.next_node;
+        self.next_node += 1;
+        self.splits.insert(innovation, id);
+        id
+    }
+}
+
+impl NodeKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            NodeKind::Input => 0,
+            NodeKind::Hidden => 1,
+            NodeKind::Output => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(NodeKind::Input),
+            1 => Ok(NodeKind::Hidden),
+            2 => Ok(NodeKind::Output),
+            _ => Err(invalid_data(format!("Unknown node kind {}", byte))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn grown_genome(innovations: &mut Innovations, rng: &mut impl Rng) -> Genome {
+        let mut genome = Genome::new(
+            4,
+            2,
+            Activation::Tanh,
+            Activation::Sigmoid,
+            innovations,
+            rng,
+        );
+        for _ in 0..5 {
+            genome.add_node(innovations, rng);
+            genome.add_connection(innovations, rng);
+        }
+        genome
+    }
+
+    #[test]
+    fn distance_counts_weights_and_unmatched_genes() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut innovations = Innovations::default();
+        let genome = grown_genome(&mut innovations, &mut rng);
+        assert_eq!(genome.distance(&genome), 0.0);
+
+        let mut heavier = genome.clone();
+        heavier.connections[0].weight += 1.0;
+        let mut grown = genome.clone();
+        grown.add_node(&mut innovations, &mut rng);
+
+        assert!(genome.distance(&heavier) > 0.0);
+        assert_eq!(genome.distance(&heavier), heavier.distance(&genome));
+        assert!(genome.distance(&grown) > 0.0);
+
+        let other_shape = Genome::new(
+            5,
+            2,
+            Activation::Tanh,
+            Activation::Sigmoid,
+            &mut innovations,
+            &mut rng,
+        );
+        assert_eq!(genome.distance(&other_shape), f64::INFINITY);
+    }
+
+    #[test]
+    fn crossover_keeps_the_fitter_parents_genes() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let mut innovations = Innovations::default();
+        let genome = grown_genome(&mut innovations, &mut rng);
+        let mut other = genome.clone();
+        for _ in 0..5 {
+            other.add_node(&mut innovations, &mut rng);
+        }
+
+        let child = genome.crossover(&other, &mut rng);
+        let innovations_of =
+            |g: &Genome| -> Vec<usize> { g.connections.iter().map(|c| c.innovation).collect() };
+        assert_eq!(innovations_of(&child), innovations_of(&genome));
+        assert_eq!(child.nodes.len(), genome.nodes.len());
+        assert!(child.clone().validated().is_ok());
+
+        let self_child = genome.crossover(&genome, &mut rng);
+        assert_eq!(self_child.distance(&genome), 0.0);
+    }
+
+    #[test]
+    fn genome_formats_round_trip() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let genome = grown_genome(&mut Innovations::default(), &mut rng);
+        let inputs = [0.1, -0.4, 0.7, 1.0];
+        let expected = genome.predict(&inputs).unwrap();
+
+        let labels = IoLabels {
+            inputs: ["a", "b", "c", "d"].map(String::from).to_vec(),
+            outputs: ["x", "y"].map(String::from).to_vec(),
+        };
+
+        let from_json = Genome::from_json(&genome.to_json(&labels).unwrap()).unwrap();
+        let from_bytes = Genome::from_bytes(&genome.to_bytes(&labels)).unwrap();
+        for (decoded, decoded_labels) in [from_json, from_bytes] {
+            assert_eq!(decoded.distance(&genome), 0.0);
+            assert_eq!(decoded.mutation(), genome.mutation());
+            assert_eq!(decoded.predict(&inputs).unwrap(), expected);
+            assert_eq!(decoded_labels, labels);
+        }
+    }
+
+    #[test]
+    fn remap_io_lines_inputs_and_outputs_up_by_label() {
+        let mut rng = StdRng::seed_from_u64(21);
+        let genome = grown_genome(&mut Innovations::default(), &mut rng);
+        let saved = IoLabels {
+            inputs: ["a", "b", "c", "d"].map(String::from).to_vec(),
+            outputs: ["x", "y"].map(String::from).to_vec(),
+        };
+        // `b` is gone, `e` and `z` are new
+        let current = IoLabels {
+            inputs: ["d", "e", "a", "c"].map(String::from).to_vec(),
+            outputs: ["y", "x", "z"].map(String::from).to_vec(),
+        };
+        let mapping = IoMapping::new(&saved, &current, vec![0.0, 0.0, 0.25]);
+        let remapped = genome.remap_io(&mapping).validated().unwrap();
+
+        let expected = genome.predict(&[0.1, 0.0, 0.7, -0.3]).unwrap();
+        let outputs = remapped.predict(&[-0.3, 0.9, 0.1, 0.7]).unwrap();
+        let (expected, outputs) = (expected.last().unwrap(), outputs.last().unwrap());
+        assert_eq!(outputs[..2], [expected[1], expected[0]]);
+        assert!((Activation::Sigmoid.normalize(outputs[2]) - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn flat_genome_matches_predict() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut innovations = Innovations::default();
+        let mut genome = Genome::new(
+            5,
+            3,
+            Activation::Tanh,
+            Activation::Sigmoid,
+            &mut innovations,
+            &mut rng,
+        );
+        for _ in 0..20 {
+            genome.add_node(&mut innovations, &mut rng);
+            genome.add_connection(&mut innovations, &mut rng);
+        }
+
+        let flat = FlatGenome::new(&genome);
+        let mut scratch = Vec::new();
+        let mut outputs = vec![0.0; 3];
+        for _ in 0..10 {
+            let inputs: Vec<f64> = (0..5).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            let expected = genome.predict(&inputs).unwrap();
+            flat.predict_into(&inputs, &mut scratch, &mut outputs)
+                .unwrap();
+            assert_eq!(&outputs, expected.last().unwrap());
+        }
+    }
+}
@@ -1,104 +1,1324 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::*;
 
+/// `NetFile` layout written by this build, files with a newer one are rejected
+pub const NET_FILE_VERSION: u32 = 5;
+const NET_BIN_MAGIC: &[u8; 4] = b"AVAN";
+
 #[derive(Clone)]
 pub struct Net {
     n_inputs: usize,
     layers: Vec<Layer>,
+    mutation: MutationParams,
+}
+
+/// Mutation parameters carried by every brain and inherited by its children
+/// Each mutation first perturbs them log-normally, so the search step evolves with the weights
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct MutationParams {
+    /// Chance of each weight being changed
+    pub rate: f64,
+    /// Largest change of a mutated weight
+    pub variation: f64,
+}
+
+/// What each input and output of a brain stands for, saved with it
+/// A later build with other sensors or actions lines the brain up by these, see `IoMapping`
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct IoLabels {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
 }
 
+/// Where every input and output of the current layout comes from in a saved brain
+pub struct IoMapping {
+    /// Saved input of each current input, None for inputs the brain never had
+    pub inputs: Vec<Option<usize>>,
+    /// Saved output of each current output, None for outputs the brain never had
+    pub outputs: Vec<Option<usize>>,
+    /// Value each current output settles on when the brain never had it
+    pub new_output_values: Vec<f64>,
+}
+
+/// Elman layers append one weight per node of their own previous output to every row
 #[derive(Clone)]
 struct Layer {
     nodes: Vec<Vec<f64>>,
+    activation: Activation,
+    kind: LayerKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum LayerKind {
+    #[default]
+    Dense,
+    /// Recurrent, the layer's previous output is fed back in as context
+    Elman,
+}
+
+/// `Net` with every weight packed into one contiguous array, row by row
+/// Gives the same outputs as `Net::predict`, without allocating per prediction
+#[derive(Clone)]
+pub struct FlatNet {
+    n_inputs: usize,
+    n_outputs: usize,
+    max_width: usize,
+    layers: Vec<FlatLayer>,
+    weights: Vec<f64>,
+}
+
+#[derive(Clone)]
+struct FlatLayer {
+    n_in: usize,
+    n_out: usize,
+    row_len: usize,
+    offset: usize,
+    activation: Activation,
+    kind: LayerKind,
+}
+
+/// Hidden state of a net between predictions, the previous output of every recurrent layer
+/// Dense layers keep an empty entry, an empty state is the same as an all zero one
+#[derive(Clone, Default, Debug)]
+pub struct NetState(pub Vec<Vec<f64>>);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Activation {
+    #[default]
+    Sigmoid,
+    Tanh,
+    Relu,
+    LeakyRelu,
+    Linear,
+    Softsign,
+}
+
+/// How a child brain is built from two parents
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Crossover {
+    /// Asexual, the child is a clone of the first parent
+    None,
+    /// Every weight comes from either parent
+    Uniform,
+    /// Every node comes from either parent, with all of its weights
+    Neuron,
+    /// Weights before a random cut come from the first parent, the rest from the second
+    SinglePoint,
+}
+
+/// Shape problems of a net or of the values passed to it, or no brain at all
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NetError {
+    /// A net needs at least an input and an output layer
+    TooFewLayers(usize),
+    /// Layer at this index has no nodes
+    EmptyLayer(usize),
+    /// Activations and layer kinds need one entry per layer after the inputs
+    LayerSpecMismatch {
+        num_layers: usize,
+        num_activations: usize,
+        num_kinds: usize,
+    },
+    InputSize {
+        expected: usize,
+        found: usize,
+    },
+    OutputSize {
+        expected: usize,
+        found: usize,
+    },
+    WeightCount {
+        expected: usize,
+        found: usize,
+    },
+    /// The cell has no compiled brain to evaluate
+    MissingBrain,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NetFormat {
+    Json,
+    Binary,
+}
+
+/// On-disk representation of a `Net`
+/// `weights[layer][node]` holds the bias first, followed by one weight per input,
+/// then one weight per node of the layer for `Elman` layers
+#[derive(Serialize, Deserialize)]
+struct NetFile {
+    version: u32,
+    n_inputs: usize,
+    layer_sizes: Vec<usize>,
+    weights: Vec<Vec<Vec<f64>>>,
+    /// Empty for version 1, where every layer used sigmoid
+    #[serde(default)]
+    activations: Vec<Activation>,
+    /// Missing before version 3, which only had dense layers
+    #[serde(default)]
+    kinds: Vec<LayerKind>,
+    /// Missing before version 4, which used the configured mutation parameters
+    #[serde(default)]
+    mutation: MutationParams,
+    /// Missing before version 5, which didn't say what the inputs and outputs were
+    #[serde(default)]
+    labels: IoLabels,
 }
 
 impl Net {
-    pub fn new(layer_sizes: Vec<usize>) -> Self {
+    /// Sigmoid activations on every layer
+    pub fn new(layer_sizes: Vec<usize>, rng: &mut impl Rng) -> Result<Self, NetError> {
+        let activations = vec![Activation::Sigmoid; layer_sizes.len().saturating_sub(1)];
+        Self::with_activations(layer_sizes, activations, rng)
+    }
+
+    /// `activations` has one entry for every layer after the inputs
+    pub fn with_activations(
+        layer_sizes: Vec<usize>,
+        activations: Vec<Activation>,
+        rng: &mut impl Rng,
+    ) -> Result<Self, NetError> {
+        let kinds = vec![LayerKind::Dense; activations.len()];
+        Self::with_layer_kinds(layer_sizes, activations, kinds, rng)
+    }
+
+    /// `activations` and `kinds` have one entry for every layer after the inputs
+    pub fn with_layer_kinds(
+        layer_sizes: Vec<usize>,
+        activations: Vec<Activation>,
+        kinds: Vec<LayerKind>,
+        rng: &mut impl Rng,
+    ) -> Result<Self, NetError> {
         if layer_sizes.len() < 2 {
-            panic!("Need at least 2 layers");
+            return Err(NetError::TooFewLayers(layer_sizes.len()));
         }
-        for &size in layer_sizes.iter() {
-            if size < 1 {
-                panic!("Empty layers not allowed");
-            }
+        if let Some(index) = layer_sizes.iter().position(|&size| size < 1) {
+            return Err(NetError::EmptyLayer(index));
+        }
+        if activations.len() != layer_sizes.len() - 1 || kinds.len() != activations.len() {
+            return Err(NetError::LayerSpecMismatch {
+                num_layers: layer_sizes.len(),
+                num_activations: activations.len(),
+                num_kinds: kinds.len(),
+            });
         }
 
         let mut layers = Vec::new();
         let first_layer_size = *layer_sizes.first().unwrap();
         let mut prev_layer_size = first_layer_size;
 
-        for &layer_size in layer_sizes[1..].iter() {
-            layers.push(Layer::new(layer_size, prev_layer_size));
+        let layer_specs = activations.iter().zip(kinds.iter());
+        for (&layer_size, (&activation, &kind)) in layer_sizes[1..].iter().zip(layer_specs) {
+            layers.push(Layer::new(
+                layer_size,
+                prev_layer_size,
+                activation,
+                kind,
+                rng,
+            ));
             prev_layer_size = layer_size;
         }
 
-        Self {
+        Ok(Self {
             layers,
             n_inputs: first_layer_size,
-        }
+            mutation: MutationParams::default(),
+        })
     }
 
-    pub fn predict(&self, inputs: &Vec<f64>) -> Vec<Vec<f64>> {
-        if inputs.len() != self.n_inputs {
-            panic!("Bad input size");
+    /// Recurrent layers start from an empty context, see `predict_with_state`
+    pub fn predict(&self, inputs: &[f64]) -> Result<Vec<Vec<f64>>, NetError> {
+        self.predict_with_state(inputs, &mut NetState::default())
+    }
+
+    /// Feeds `state` to the recurrent layers and replaces it with their new outputs
+    pub fn predict_with_state(
+        &self,
+        inputs: &[f64],
+        state: &mut NetState,
+    ) -> Result<Vec<Vec<f64>>, NetError> {
+        NetError::check_input_size(self.n_inputs, inputs)?;
+        if state.0.len() != self.layers.len() {
+            *state = self.initial_state();
         }
 
         let mut outputs = Vec::new();
-        outputs.push(inputs.clone());
+        outputs.push(inputs.to_vec());
         for (layer_index, layer) in self.layers.iter().enumerate() {
-            let layer_results = layer.predict(&outputs[layer_index]);
+            let layer_results = layer.predict(&outputs[layer_index], &state.0[layer_index]);
+            if layer.kind == LayerKind::Elman {
+                state.0[layer_index].clone_from(&layer_results);
+            }
             outputs.push(layer_results);
         }
 
-        outputs
+        Ok(outputs)
+    }
+
+    /// All zero context, what a newborn cell starts with
+    pub fn initial_state(&self) -> NetState {
+        NetState(
+            self.layers
+                .iter()
+                .map(|l| match l.kind {
+                    LayerKind::Dense => Vec::new(),
+                    LayerKind::Elman => vec![0.0; l.nodes.len()],
+                })
+                .collect(),
+        )
+    }
+
+    /// One pass of stochastic gradient descent on the squared error, samples in order
+    /// Recurrent layers see the context left by the previous sample as a fixed input,
+    /// the error isn't propagated back through time
+    /// Returns the mean loss of the pass
+    pub fn train(
+        &mut self,
+        samples: &[(Vec<f64>, Vec<f64>)],
+        learning_rate: f64,
+    ) -> Result<f64, NetError> {
+        let n_outputs = self.layers.last().unwrap().nodes.len();
+        let mut state = self.initial_state();
+        let mut total_loss = 0.0;
+
+        for (inputs, targets) in samples {
+            NetError::check_input_size(self.n_inputs, inputs)?;
+            if targets.len() != n_outputs {
+                return Err(NetError::OutputSize {
+                    expected: n_outputs,
+                    found: targets.len(),
+                });
+            }
+
+            // Forward pass, keeping the inputs and pre-activations of every layer
+            let contexts = state.0.clone();
+            let mut activations = vec![inputs.clone()];
+            let mut pre_activations = Vec::new();
+            for (layer_index, layer) in self.layers.iter().enumerate() {
+                let sums: Vec<f64> = layer
+                    .nodes
+                    .iter()
+                    .map(|node| {
+                        layer.weighted_sum(node, &activations[layer_index], &contexts[layer_index])
+                    })
+                    .collect();
+                let results: Vec<f64> = sums.iter().map(|v| layer.activation.apply(*v)).collect();
+                if layer.kind == LayerKind::Elman {
+                    state.0[layer_index].clone_from(&results);
+                }
+                pre_activations.push(sums);
+                activations.push(results);
+            }
+
+            let last = self.layers.len() - 1;
+            let outputs = &activations[last + 1];
+            total_loss += outputs
+                .iter()
+                .zip(targets.iter())
+                .map(|(o, t)| 0.5 * (o - t).powi(2))
+                .sum::<f64>();
+            let mut deltas: Vec<f64> = outputs
+                .iter()
+                .zip(targets.iter())
+                .zip(pre_activations[last].iter())
+                .map(|((o, t), y)| (o - t) * self.layers[last].activation.derivative(*y))
+                .collect();
+
+            for layer_index in (0..self.layers.len()).rev() {
+                // Deltas of the layer below are taken before this layer's weights move
+                let below_deltas = (layer_index > 0).then(|| {
+                    let below = &self.layers[layer_index - 1];
+                    (0..activations[layer_index].len())
+                        .map(|j| {
+                            let error: f64 = self.layers[layer_index]
+                                .nodes
+                                .iter()
+                                .zip(deltas.iter())
+                                .map(|(node, d)| node[j + 1] * d)
+                                .sum();
+                            error
+                                * below
+                                    .activation
+                                    .derivative(pre_activations[layer_index - 1][j])
+                        })
+                        .collect::<Vec<f64>>()
+                });
+
+                let layer_inputs = &activations[layer_index];
+                let n_in = layer_inputs.len();
+                for (node, delta) in self.layers[layer_index].nodes.iter_mut().zip(deltas.iter()) {
+                    let step = learning_rate * delta;
+                    node[0] -= step;
+                    for (weight, value) in node[1..=n_in].iter_mut().zip(layer_inputs.iter()) {
+                        *weight -= step * value;
+                    }
+                    for (weight, value) in node[n_in + 1..]
+                        .iter_mut()
+                        .zip(contexts[layer_index].iter())
+                    {
+                        *weight -= step * value;
+                    }
+                }
+
+                match below_deltas {
+                    Some(d) => deltas = d,
+                    None => break,
+                }
+            }
+        }
+
+        Ok(total_loss / samples.len().max(1) as f64)
+    }
+
+    pub fn layer_kinds(&self) -> Vec<LayerKind> {
+        self.layers.iter().map(|l| l.kind).collect()
+    }
+
+    /// Adapts the mutation parameters, then mutates the weights with the new ones
+    pub fn mutate(&mut self, rng: &mut impl Rng) {
+        self.mutation.adapt(rng);
+        let params = self.mutation;
+        self.layers.iter_mut().for_each(|l| l.mutate(&params, rng));
+    }
+
+    pub fn mutation(&self) -> MutationParams {
+        self.mutation
+    }
+
+    pub fn activations(&self) -> Vec<Activation> {
+        self.layers.iter().map(|l| l.activation).collect()
+    }
+
+    /// Activation of the output layer
+    pub fn output_activation(&self) -> Activation {
+        self.layers.last().unwrap().activation
+    }
+
+    /// Child of `self` and `other`, activations are taken from `self`
+    /// Parents with different architectures can't be mixed, so `self` is cloned instead
+    pub fn crossover(&self, other: &Net, mode: Crossover, rng: &mut impl Rng) -> Net {
+        let mut child = self.clone();
+        if mode == Crossover::None || self.layer_sizes() != other.layer_sizes() {
+            return child;
+        }
+        child.mutation = self.mutation.blend(&other.mutation);
+
+        // Only single point draws a cut, the other modes leave the rng stream alone
+        let cut = match mode {
+            Crossover::SinglePoint => rng.gen_range(0..=self.num_weights()),
+            _ => 0,
+        };
+        let mut weight_index = 0;
+
+        for (layer, other_layer) in child.layers.iter_mut().zip(other.layers.iter()) {
+            for (node, other_node) in layer.nodes.iter_mut().zip(other_layer.nodes.iter()) {
+                match mode {
+                    Crossover::Uniform => {
+                        for (w, other_w) in node.iter_mut().zip(other_node.iter()) {
+                            if rng.gen_bool(0.5) {
+                                *w = *other_w;
+                            }
+                        }
+                    }
+                    Crossover::Neuron => {
+                        if rng.gen_bool(0.5) {
+                            node.clone_from(other_node);
+                        }
+                    }
+                    Crossover::SinglePoint => {
+                        for (w, other_w) in node.iter_mut().zip(other_node.iter()) {
+                            if weight_index >= cut {
+                                *w = *other_w;
+                            }
+                            weight_index += 1;
+                        }
+                    }
+                    Crossover::None => {}
+                }
+            }
+        }
+
+        child
+    }
+
+    /// Weights of the `layer`th layer after the inputs, one row per node with the bias first
+    /// Rows of `Elman` layers end with the weights of the layer's own previous output
+    pub fn layer_weights(&self, layer: usize) -> &[Vec<f64>] {
+        &self.layers[layer].nodes
+    }
+
+    pub fn num_weights(&self) -> usize {
+        self.layers.iter().map(|l| l.num_weights()).sum()
+    }
+
+    /// Every weight in one vector, layer by layer and row by row like `layer_weights`
+    pub fn weights(&self) -> Vec<f64> {
+        self.layers
+            .iter()
+            .flat_map(|l| l.nodes.iter().flatten())
+            .copied()
+            .collect()
+    }
+
+    /// Replaces every weight, `weights` is laid out the same way `weights` returns them
+    pub fn set_weights(&mut self, weights: &[f64]) -> Result<(), NetError> {
+        if weights.len() != self.num_weights() {
+            return Err(NetError::WeightCount {
+                expected: self.num_weights(),
+                found: weights.len(),
+            });
+        }
+
+        let targets = self
+            .layers
+            .iter_mut()
+            .flat_map(|l| l.nodes.iter_mut().flatten());
+        for (target, weight) in targets.zip(weights.iter()) {
+            *target = *weight;
+        }
+
+        Ok(())
     }
 
-    pub fn mutate(&mut self) {
-        self.layers.iter_mut().for_each(|l| l.mutate());
+    /// Mean absolute difference between the weights of both nets
+    /// Nets with different architectures are infinitely far apart
+    pub fn distance(&self, other: &Net) -> f64 {
+        if self.layer_sizes() != other.layer_sizes() || self.layer_kinds() != other.layer_kinds() {
+            return f64::INFINITY;
+        }
+
+        let weights = self.layers.iter().flat_map(|l| l.nodes.iter().flatten());
+        let other_weights = other.layers.iter().flat_map(|l| l.nodes.iter().flatten());
+        let (total, count) = weights
+            .zip(other_weights)
+            .fold((0.0, 0), |(total, count), (w, o)| {
+                (total + (w - o).abs(), count + 1)
+            });
+
+        total / count.max(1) as f64
+    }
+
+    /// Sizes of every layer, inputs included
+    pub fn layer_sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![self.n_inputs];
+        sizes.extend(self.layers.iter().map(|l| l.nodes.len()));
+        sizes
+    }
+
+    pub fn save(&self, path: &Path, format: NetFormat, labels: &IoLabels) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let bytes = match format {
+            NetFormat::Json => self.to_json(labels)?.into_bytes(),
+            NetFormat::Binary => self.to_bytes(labels),
+        };
+        fs::write(path, bytes)
+    }
+
+    /// Decodes either format, binary files are told apart by their magic bytes
+    /// The labels are empty for files from before version 5
+    pub fn decode(bytes: &[u8]) -> io::Result<(Self, IoLabels)> {
+        if bytes.starts_with(NET_BIN_MAGIC) {
+            Self::from_bytes(bytes)
+        } else {
+            let text = std::str::from_utf8(bytes).map_err(invalid_data)?;
+            Self::from_json(text)
+        }
+    }
+
+    pub fn check_arch(&self, arch: &[usize]) -> io::Result<()> {
+        let sizes = self.layer_sizes();
+        if sizes != arch {
+            return Err(invalid_data(format!(
+                "Architecture mismatch, expected {:?} found {:?}",
+                arch, sizes
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn to_json(&self, labels: &IoLabels) -> io::Result<String> {
+        serde_json::to_string_pretty(&self.to_file(labels)).map_err(invalid_data)
+    }
+
+    pub fn from_json(text: &str) -> io::Result<(Self, IoLabels)> {
+        let file: NetFile = serde_json::from_str(text).map_err(invalid_data)?;
+        Self::from_file(file)
+    }
+
+    /// Compact little endian encoding
+    /// magic, version, n_inputs, num layers, layer sizes,
+    /// one activation byte and one kind byte per layer,
+    /// mutation rate and variation as f64, the input and output labels, then every weight as f64
+    pub fn to_bytes(&self, labels: &IoLabels) -> Vec<u8> {
+        let file = self.to_file(labels);
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(NET_BIN_MAGIC);
+        bytes.extend_from_slice(&file.version.to_le_bytes());
+        bytes.extend_from_slice(&(file.n_inputs as u32).to_le_bytes());
+        bytes.extend_from_slice(&(file.layer_sizes.len() as u32).to_le_bytes());
+        for size in file.layer_sizes.iter() {
+            bytes.extend_from_slice(&(*size as u32).to_le_bytes());
+        }
+        for activation in file.activations.iter() {
+            bytes.push(activation.to_byte());
+        }
+        for kind in file.kinds.iter() {
+            bytes.push(kind.to_byte());
+        }
+        bytes.extend_from_slice(&file.mutation.rate.to_le_bytes());
+        bytes.extend_from_slice(&file.mutation.variation.to_le_bytes());
+        file.labels.write(&mut bytes);
+        for weight in file.weights.iter().flatten().flatten() {
+            bytes.extend_from_slice(&weight.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(Self, IoLabels)> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.take(NET_BIN_MAGIC.len())? != NET_BIN_MAGIC {
+            return Err(invalid_data("Not a brain file"));
+        }
+
+        let version = reader.read_u32()?;
+        let n_inputs = reader.read_u32()? as usize;
+        let num_layers = reader.read_u32()? as usize;
+        let mut layer_sizes = Vec::new();
+        for _ in 0..num_layers {
+            layer_sizes.push(reader.read_u32()? as usize);
+        }
+        if layer_sizes.is_empty() {
+            return Err(invalid_data("Missing layer sizes"));
+        }
+        let mut activations = Vec::new();
+        if version >= 2 {
+            for _ in 1..num_layers {
+                activations.push(Activation::from_byte(reader.read_u8()?)?);
+            }
+        }
+        let mut kinds = Vec::new();
+        if version >= 3 {
+            for _ in 1..num_layers {
+                kinds.push(LayerKind::from_byte(reader.read_u8()?)?);
+            }
+        }
+        let mut mutation = MutationParams::default();
+        if version >= 4 {
+            mutation.rate = reader.read_f64()?;
+            mutation.variation = reader.read_f64()?;
+        }
+        let labels = match version >= 5 {
+            true => IoLabels::read(&mut reader)?,
+            false => IoLabels::default(),
+        };
+
+        let mut weights = Vec::new();
+        for (i, pair) in layer_sizes.windows(2).enumerate() {
+            let kind = kinds.get(i).copied().unwrap_or_default();
+            let mut layer = Vec::new();
+            for _ in 0..pair[1] {
+                let mut node = Vec::new();
+                for _ in 0..kind.row_len(pair[0], pair[1]) {
+                    node.push(reader.read_f64()?);
+                }
+                layer.push(node);
+            }
+            weights.push(layer);
+        }
+
+        Self::from_file(NetFile {
+            version,
+            n_inputs,
+            layer_sizes,
+            weights,
+            activations,
+            kinds,
+            mutation,
+            labels,
+        })
+    }
+
+    /// Default file name used when exporting a cell's brain
+    pub fn export_path(cell_id: u32, format: NetFormat) -> PathBuf {
+        Path::new(BRAINS_DIR).join(format!("cell-{}.{}", cell_id, format.extension()))
+    }
+
+    fn to_file(&self, labels: &IoLabels) -> NetFile {
+        NetFile {
+            version: NET_FILE_VERSION,
+            n_inputs: self.n_inputs,
+            layer_sizes: self.layer_sizes(),
+            weights: self.layers.iter().map(|l| l.nodes.clone()).collect(),
+            activations: self.activations(),
+            kinds: self.layer_kinds(),
+            mutation: self.mutation,
+            labels: labels.clone(),
+        }
+    }
+
+    fn from_file(file: NetFile) -> io::Result<(Self, IoLabels)> {
+        if file.version > NET_FILE_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported brain version {}",
+                file.version
+            )));
+        }
+        if file.layer_sizes.len() < 2 || file.layer_sizes.contains(&0) {
+            return Err(invalid_data("Need at least 2 non empty layers"));
+        }
+        if file.layer_sizes[0] != file.n_inputs {
+            return Err(invalid_data("Input size doesn't match the first layer"));
+        }
+        if file.weights.len() != file.layer_sizes.len() - 1 {
+            return Err(invalid_data("Layer count doesn't match the weights"));
+        }
+        let activations = if file.activations.is_empty() {
+            vec![Activation::Sigmoid; file.weights.len()]
+        } else {
+            file.activations
+        };
+        if activations.len() != file.weights.len() {
+            return Err(invalid_data("Layer count doesn't match the activations"));
+        }
+        let kinds = if file.kinds.is_empty() {
+            vec![LayerKind::Dense; file.weights.len()]
+        } else {
+            file.kinds
+        };
+        if kinds.len() != file.weights.len() {
+            return Err(invalid_data("Layer count doesn't match the layer kinds"));
+        }
+        file.mutation.check()?;
+        file.labels
+            .check(file.n_inputs, file.layer_sizes[file.layer_sizes.len() - 1])?;
+
+        let mut layers = Vec::new();
+        let layer_data = file.weights.into_iter().zip(activations).zip(kinds);
+        for (((nodes, activation), kind), pair) in layer_data.zip(file.layer_sizes.windows(2)) {
+            let row_len = kind.row_len(pair[0], pair[1]);
+            if nodes.len() != pair[1] || nodes.iter().any(|n| n.len() != row_len) {
+                return Err(invalid_data("Weights don't match the layer sizes"));
+            }
+            layers.push(Layer {
+                nodes,
+                activation,
+                kind,
+            });
+        }
+
+        let net = Self {
+            n_inputs: file.n_inputs,
+            layers,
+            mutation: file.mutation,
+        };
+        Ok((net, file.labels))
+    }
+
+    /// Copy wired up to the current inputs and outputs
+    /// New inputs get zero weights, new outputs zero weights and a bias that gives their
+    /// `new_output_values`, inputs and outputs the layout no longer has are dropped
+    pub fn remap_io(&self, mapping: &IoMapping) -> Net {
+        let mut net = self.clone();
+        // Rows are the bias, one weight per input, then the Elman context weights
+        for row in net.layers[0].nodes.iter_mut() {
+            let mut remapped = vec![row[0]];
+            remapped.extend(mapping.inputs.iter().map(|i| i.map_or(0.0, |i| row[1 + i])));
+            remapped.extend_from_slice(&row[1 + self.n_inputs..]);
+            *row = remapped;
+        }
+        net.n_inputs = mapping.inputs.len();
+
+        let last = net.layers.last_mut().unwrap();
+        let old_nodes = std::mem::take(&mut last.nodes);
+        let n_in = old_nodes[0].len() - last.kind.row_len(0, old_nodes.len());
+        for (output, source) in mapping.outputs.iter().enumerate() {
+            let mut row = match source {
+                Some(i) => old_nodes[*i][..1 + n_in].to_vec(),
+                None => {
+                    let mut row = vec![0.0; 1 + n_in];
+                    row[0] = last.activation.bias_for(mapping.new_output_values[output]);
+                    row
+                }
+            };
+            // Elman context weights follow the outputs they read from
+            if last.kind == LayerKind::Elman {
+                row.extend(mapping.outputs.iter().map(|from| match (source, from) {
+                    (Some(i), Some(j)) => old_nodes[*i][1 + n_in + j],
+                    _ => 0.0,
+                }));
+            }
+            last.nodes.push(row);
+        }
+
+        net
+    }
+}
+
+impl IoLabels {
+    /// Empty labels are unknown, otherwise there has to be one per input and output
+    pub(crate) fn check(&self, n_inputs: usize, n_outputs: usize) -> io::Result<()> {
+        if *self == IoLabels::default() {
+            return Ok(());
+        }
+        if self.inputs.len() != n_inputs || self.outputs.len() != n_outputs {
+            return Err(invalid_data(format!(
+                "{} input and {} output labels for {} inputs and {} outputs",
+                self.inputs.len(),
+                self.outputs.len(),
+                n_inputs,
+                n_outputs
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Label counts, then every label as its byte length and utf-8 bytes
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) {
+        for labels in [&self.inputs, &self.outputs] {
+            bytes.extend_from_slice(&(labels.len() as u32).to_le_bytes());
+            for label in labels.iter() {
+                bytes.extend_from_slice(&(label.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(label.as_bytes());
+            }
+        }
+    }
+
+    pub(crate) fn read(reader: &mut ByteReader) -> io::Result<Self> {
+        let mut read_labels = || -> io::Result<Vec<String>> {
+            let mut labels = Vec::new();
+            for _ in 0..reader.read_u32()? {
+                let len = reader.read_u32()? as usize;
+                let label = std::str::from_utf8(reader.take(len)?).map_err(invalid_data)?;
+                labels.push(label.to_string());
+            }
+            Ok(labels)
+        };
+        let inputs = read_labels()?;
+        let outputs = read_labels()?;
+
+        Ok(Self { inputs, outputs })
     }
 }
 
+impl IoMapping {
+    /// Lines `saved` up with `current` by label
+    /// `new_output_values` has one value per current output
+    pub fn new(saved: &IoLabels, current: &IoLabels, new_output_values: Vec<f64>) -> Self {
+        let find = |labels: &[String], label: &String| labels.iter().position(|l| l == label);
+        Self {
+            inputs: current
+                .inputs
+                .iter()
+                .map(|l| find(&saved.inputs, l))
+                .collect(),
+            outputs: current
+                .outputs
+                .iter()
+                .map(|l| find(&saved.outputs, l))
+                .collect(),
+            new_output_values,
+        }
+    }
+
+    /// Every input and output is where it was saved
+    pub fn is_identity(&self, n_saved_inputs: usize, n_saved_outputs: usize) -> bool {
+        let in_place = |map: &[Option<usize>], n: usize| {
+            map.len() == n && map.iter().enumerate().all(|(i, s)| *s == Some(i))
+        };
+        in_place(&self.inputs, n_saved_inputs) && in_place(&self.outputs, n_saved_outputs)
+    }
+}
+
+impl FlatNet {
+    pub fn new(net: &Net) -> Self {
+        let mut layers = Vec::new();
+        let mut weights = Vec::new();
+        let mut n_in = net.n_inputs;
+        for layer in net.layers.iter() {
+            let n_out = layer.nodes.len();
+            layers.push(FlatLayer {
+                n_in,
+                n_out,
+                row_len: layer.kind.row_len(n_in, n_out),
+                offset: weights.len(),
+                activation: layer.activation,
+                kind: layer.kind,
+            });
+            weights.extend(layer.nodes.iter().flatten());
+            n_in = n_out;
+        }
+
+        Self {
+            n_inputs: net.n_inputs,
+            n_outputs: n_in,
+            max_width: net.layer_sizes().into_iter().max().unwrap(),
+            layers,
+            weights,
+        }
+    }
+
+    pub fn n_inputs(&self) -> usize {
+        self.n_inputs
+    }
+
+    pub fn n_outputs(&self) -> usize {
+        self.n_outputs
+    }
+
+    /// Writes the output layer into `outputs`
+    /// `scratch` holds the current and next layer values, pass the same one every call
+    pub fn predict_into(
+        &self,
+        inputs: &[f64],
+        state: &mut NetState,
+        scratch: &mut Vec<f64>,
+        outputs: &mut [f64],
+    ) -> Result<(), NetError> {
+        NetError::check_input_size(self.n_inputs, inputs)?;
+        if outputs.len() != self.n_outputs {
+            return Err(NetError::OutputSize {
+                expected: self.n_outputs,
+                found: outputs.len(),
+            });
+        }
+        if state.0.len() != self.layers.len() {
+            *state = self.initial_state();
+        }
+
+        scratch.resize(2 * self.max_width, 0.0);
+        let (mut current, mut next) = scratch.split_at_mut(self.max_width);
+        current[..inputs.len()].copy_from_slice(inputs);
+
+        for (layer, context) in self.layers.iter().zip(state.0.iter_mut()) {
+            let weights = &self.weights[layer.offset..layer.offset + layer.row_len * layer.n_out];
+            for (node, row) in weights.chunks_exact(layer.row_len).enumerate() {
+                // Bias, inputs, then context, the order `Layer::predict` sums in
+                let mut total = row[0];
+                for (weight, value) in row[1..=layer.n_in].iter().zip(current.iter()) {
+                    total += weight * value;
+                }
+                for (weight, value) in row[layer.n_in + 1..].iter().zip(context.iter()) {
+                    total += weight * value;
+                }
+                next[node] = layer.activation.apply(total);
+            }
+
+            if layer.kind == LayerKind::Elman {
+                context.clear();
+                context.extend_from_slice(&next[..layer.n_out]);
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        outputs.copy_from_slice(&current[..self.n_outputs]);
+        Ok(())
+    }
+
+    fn initial_state(&self) -> NetState {
+        NetState(
+            self.layers
+                .iter()
+                .map(|l| match l.kind {
+                    LayerKind::Dense => Vec::new(),
+                    LayerKind::Elman => vec![0.0; l.n_out],
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Crossover {
+    pub const ALL: [Crossover; 4] = [
+        Crossover::None,
+        Crossover::Uniform,
+        Crossover::Neuron,
+        Crossover::SinglePoint,
+    ];
+
+    pub fn get_label(&self) -> &str {
+        match self {
+            Crossover::None => "None (asexual)",
+            Crossover::Uniform => "Uniform",
+            Crossover::Neuron => "Per neuron",
+            Crossover::SinglePoint => "Single point",
+        }
+    }
+}
+
+impl Activation {
+    pub const ALL: [Activation; 6] = [
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Relu,
+        Activation::LeakyRelu,
+        Activation::Linear,
+        Activation::Softsign,
+    ];
+
+    pub fn apply(&self, y: f64) -> f64 {
+        match self {
+            Activation::Sigmoid => 1f64 / (1f64 + (-y).exp()),
+            Activation::Tanh => y.tanh(),
+            Activation::Relu => y.max(0.0),
+            Activation::LeakyRelu => {
+                if y > 0.0 {
+                    y
+                } else {
+                    0.01 * y
+                }
+            }
+            Activation::Linear => y,
+            Activation::Softsign => y / (1.0 + y.abs()),
+        }
+    }
+
+    /// Bias that makes a node without inputs give `value` once normalized,
+    /// or as close as it gets within -10..10
+    pub fn bias_for(&self, value: f64) -> f64 {
+        let (mut low, mut high) = (-10.0, 10.0);
+        // Every activation is non decreasing, so bisection finds it
+        for _ in 0..50 {
+            let mid = (low + high) / 2.0;
+            if self.normalize(self.apply(mid)) < value {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        (low + high) / 2.0
+    }
+
+    /// Maps an activated value into 0..1,
+    /// so the output thresholds mean the same thing for every activation
+    pub fn normalize(&self, v: f64) -> f64 {
+        match self {
+            Activation::Sigmoid => v,
+            Activation::Tanh | Activation::Softsign => (v + 1.0) / 2.0,
+            Activation::Relu | Activation::LeakyRelu | Activation::Linear => {
+                Activation::Sigmoid.apply(v)
+            }
+        }
+    }
+
+    /// Derivative of `apply` at the pre-activation `y`
+    pub fn derivative(&self, y: f64) -> f64 {
+        match self {
+            Activation::Sigmoid => {
+                let s = self.apply(y);
+                s * (1.0 - s)
+            }
+            Activation::Tanh => 1.0 - y.tanh().powi(2),
+            Activation::Relu => {
+                if y > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Activation::LeakyRelu => {
+                if y > 0.0 {
+                    1.0
+                } else {
+                    0.01
+                }
+            }
+            Activation::Linear => 1.0,
+            Activation::Softsign => 1.0 / (1.0 + y.abs()).powi(2),
+        }
+    }
+
+    /// Inverse of `normalize`, `v` has to be strictly inside 0..1
+    pub fn denormalize(&self, v: f64) -> f64 {
+        match self {
+            Activation::Sigmoid => v,
+            Activation::Tanh | Activation::Softsign => v * 2.0 - 1.0,
+            Activation::Relu | Activation::LeakyRelu | Activation::Linear => (v / (1.0 - v)).ln(),
+        }
+    }
+
+    pub fn get_label(&self) -> &str {
+        match self {
+            Activation::Sigmoid => "sigmoid",
+            Activation::Tanh => "tanh",
+            Activation::Relu => "relu",
+            Activation::LeakyRelu => "leaky relu",
+            Activation::Linear => "linear",
+            Activation::Softsign => "softsign",
+        }
+    }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        Self::ALL.iter().position(|a| *a == self).unwrap() as u8
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> io::Result<Self> {
+        Self::ALL
+            .get(byte as usize)
+            .copied()
+            .ok_or_else(|| invalid_data(format!("Unknown activation {}", byte)))
+    }
+}
+
+impl LayerKind {
+    pub fn get_label(&self) -> &str {
+        match self {
+            LayerKind::Dense => "dense",
+            LayerKind::Elman => "elman",
+        }
+    }
+
+    /// Weights per node, bias included
+    fn row_len(&self, prev_layer_size: usize, layer_size: usize) -> usize {
+        match self {
+            LayerKind::Dense => prev_layer_size + 1,
+            LayerKind::Elman => prev_layer_size + 1 + layer_size,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            LayerKind::Dense => 0,
+            LayerKind::Elman => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(LayerKind::Dense),
+            1 => Ok(LayerKind::Elman),
+            _ => Err(invalid_data(format!("Unknown layer kind {}", byte))),
+        }
+    }
+}
+
+impl NetFormat {
+    pub fn extension(&self) -> &str {
+        match self {
+            NetFormat::Json => "json",
+            NetFormat::Binary => "bin",
+        }
+    }
+}
+
+pub(crate) struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.bytes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Brain file is truncated",
+            ));
+        }
+
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub(crate) fn read_f64(&mut self) -> io::Result<f64> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(buf))
+    }
+}
+
+impl Default for MutationParams {
+    fn default() -> Self {
+        Self {
+            rate: BRAIN_MUTATION_RATE,
+            variation: BRAIN_MUTATION_VARIATION,
+        }
+    }
+}
+
+impl MutationParams {
+    /// Multiplies both parameters by `exp(tau * N(0, 1))`, kept within the configured limits
+    pub fn adapt(&mut self, rng: &mut impl Rng) {
+        let (min_rate, max_rate) = BRAIN_MUTATION_RATE_LIMITS;
+        let (min_variation, max_variation) = BRAIN_MUTATION_VARIATION_LIMITS;
+        let tau = BRAIN_SELF_ADAPTATION_RATE;
+
+        self.rate = (self.rate * (tau * sample_normal(rng)).exp()).clamp(min_rate, max_rate);
+        self.variation =
+            (self.variation * (tau * sample_normal(rng)).exp()).clamp(min_variation, max_variation);
+    }
+
+    /// Geometric mean of both parents, the parameters are adapted on a log scale
+    pub fn blend(&self, other: &MutationParams) -> MutationParams {
+        MutationParams {
+            rate: (self.rate * other.rate).sqrt(),
+            variation: (self.variation * other.variation).sqrt(),
+        }
+    }
+
+    pub(crate) fn check(&self) -> io::Result<()> {
+        let valid = |v: f64| v.is_finite() && v > 0.0;
+        if !valid(self.rate) || self.rate > 1.0 || !valid(self.variation) {
+            return Err(invalid_data("Invalid mutation parameters"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Standard normal sample, Box-Muller
+pub(crate) fn sample_normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+    let u2: f64 = rng.gen_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+impl NetError {
+    pub(crate) fn check_input_size(expected: usize, inputs: &[f64]) -> Result<(), NetError> {
+        if inputs.len() != expected {
+            return Err(NetError::InputSize {
+                expected,
+                found: inputs.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::TooFewLayers(n) => write!(f, "Need at least 2 layers, found {}", n),
+            NetError::EmptyLayer(index) => write!(f, "Layer {} is empty", index),
+            NetError::LayerSpecMismatch {
+                num_layers,
+                num_activations,
+                num_kinds,
+            } => write!(
+                f,
+                "Need one activation and kind per layer after the inputs, found {} layers, {} activations and {} kinds",
+                num_layers, num_activations, num_kinds
+            ),
+            NetError::InputSize { expected, found } => {
+                write!(f, "Expected {} inputs, found {}", expected, found)
+            }
+            NetError::OutputSize { expected, found } => {
+                write!(f, "Expected {} outputs, found {}", expected, found)
+            }
+            NetError::WeightCount { expected, found } => {
+                write!(f, "Expected {} weights, found {}", expected, found)
+            }
+            NetError::MissingBrain => write!(f, "No compiled brain"),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<NetError> for io::Error {
+    fn from(err: NetError) -> Self {
+        invalid_data(err)
+    }
+}
+
+pub(crate) fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
 impl Layer {
-    fn new(layer_size: usize, prev_layer_size: usize) -> Self {
-        let mut rng = rand::thread_rng();
+    fn new(
+        layer_size: usize,
+        prev_layer_size: usize,
+        activation: Activation,
+        kind: LayerKind,
+        rng: &mut impl Rng,
+    ) -> Self {
         let mut nodes: Vec<Vec<f64>> = Vec::new();
 
         for _ in 0..layer_size {
             let mut node: Vec<f64> = Vec::new();
-            for _ in 0..prev_layer_size + 1 {
+            for _ in 0..kind.row_len(prev_layer_size, layer_size) {
                 let random_weight: f64 = rng.gen_range(-1.0f64..1.0f64);
                 node.push(random_weight);
             }
             nodes.push(node);
         }
 
-        Self { nodes }
+        Self {
+            nodes,
+            activation,
+            kind,
+        }
     }
 
-    fn predict(&self, inputs: &Vec<f64>) -> Vec<f64> {
+    /// `context` is empty for dense layers
+    fn predict(&self, inputs: &[f64], context: &[f64]) -> Vec<f64> {
         let mut layer_results = Vec::new();
         for node in self.nodes.iter() {
-            layer_results.push(self.sigmoid(self.dot_prod(&node, &inputs)));
+            let total = self.weighted_sum(node, inputs, context);
+            layer_results.push(self.activation.apply(total));
         }
 
         layer_results
     }
 
-    fn mutate(&mut self) {
-        let mut rng = rand::thread_rng();
+    /// Pre-activation of `node`, context weights come after the input weights
+    fn weighted_sum(&self, node: &[f64], inputs: &[f64], context: &[f64]) -> f64 {
+        let mut total = self.dot_prod(node, inputs);
+        for (weight, value) in node[inputs.len() + 1..].iter().zip(context.iter()) {
+            total += weight * value;
+        }
+
+        total
+    }
+
+    fn mutate(&mut self, params: &MutationParams, rng: &mut impl Rng) {
         for n in self.nodes.iter_mut() {
             for val in n.iter_mut() {
-                if rng.gen_range(0.0..1.0) >= BRAIN_MUTATION_RATE {
+                if rng.gen_range(0.0..1.0) >= params.rate {
                     continue;
                 }
 
-                *val += rng.gen_range(-BRAIN_MUTATION_VARIATION..BRAIN_MUTATION_VARIATION) as f64;
+                *val += rng.gen_range(-params.variation..params.variation);
             }
         }
     }
 
-    fn dot_prod(&self, node: &Vec<f64>, values: &Vec<f64>) -> f64 {
+    fn num_weights(&self) -> usize {
+        self.nodes.iter().map(|n| n.len()).sum()
+    }
+
+    fn dot_prod(&self, node: &[f64], values: &[f64]) -> f64 {
         let mut it = node.iter();
         let mut total = *it.next().unwrap();
         for (weight, value) in it.zip(values.iter()) {
@@ -107,8 +1327,175 @@ impl Layer {
 
         total
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn random_inputs(n: usize, rng: &mut impl Rng) -> Vec<f64> {
+        (0..n).map(|_| rng.gen_range(-1.0..1.0)).collect()
+    }
+
+    /// Runs both nets over the same inputs for a few steps, so recurrent state is exercised
+    fn assert_flat_matches(net: &Net, rng: &mut impl Rng) {
+        let flat = FlatNet::new(net);
+        let mut state = NetState::default();
+        let mut flat_state = NetState::default();
+        let mut scratch = Vec::new();
+        let mut outputs = vec![0.0; flat.n_outputs()];
+
+        for _ in 0..5 {
+            let inputs = random_inputs(flat.n_inputs(), rng);
+            let expected = net.predict_with_state(&inputs, &mut state).unwrap();
+            flat.predict_into(&inputs, &mut flat_state, &mut scratch, &mut outputs)
+                .unwrap();
+            assert_eq!(&outputs, expected.last().unwrap());
+        }
+    }
+
+    #[test]
+    fn net_formats_round_trip() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut net = Net::with_layer_kinds(
+            vec![3, 4, 2],
+            vec![Activation::LeakyRelu, Activation::Softsign],
+            vec![LayerKind::Elman, LayerKind::Dense],
+            &mut rng,
+        )
+        .unwrap();
+        net.mutate(&mut rng);
+
+        let inputs = [0.1, -0.4, 0.7];
+        let expected = net.predict(&inputs).unwrap();
+        let labels = IoLabels {
+            inputs: ["a", "b", "c"].map(String::from).to_vec(),
+            outputs: ["x", "y"].map(String::from).to_vec(),
+        };
+        for bytes in [
+            net.to_json(&labels).unwrap().into_bytes(),
+            net.to_bytes(&labels),
+        ] {
+            let (decoded, decoded_labels) = Net::decode(&bytes).unwrap();
+            assert_eq!(decoded_labels, labels);
+            assert_eq!(decoded.layer_sizes(), net.layer_sizes());
+            assert_eq!(decoded.activations(), net.activations());
+            assert_eq!(decoded.layer_kinds(), net.layer_kinds());
+            assert_eq!(decoded.mutation(), net.mutation());
+            assert_eq!(decoded.weights(), net.weights());
+            assert_eq!(decoded.predict(&inputs).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn remap_io_lines_inputs_and_outputs_up_by_label() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let net = Net::with_layer_kinds(
+            vec![3, 4, 2],
+            vec![Activation::Tanh, Activation::Sigmoid],
+            vec![LayerKind::Dense, LayerKind::Elman],
+            &mut rng,
+        )
+        .unwrap();
+        let saved = IoLabels {
+            inputs: ["a", "b", "c"].map(String::from).to_vec(),
+            outputs: ["x", "y"].map(String::from).to_vec(),
+        };
+        // `b` is gone, `d` and `z` are new
+        let current = IoLabels {
+            inputs: ["c", "d", "a"].map(String::from).to_vec(),
+            outputs: ["y", "z", "x"].map(String::from).to_vec(),
+        };
+        let mapping = IoMapping::new(&saved, &current, vec![0.0, 0.8, 0.0]);
+        let remapped = net.remap_io(&mapping);
+        assert_eq!(remapped.layer_sizes(), vec![3, 4, 3]);
+
+        let (mut state, mut remapped_state) = (NetState::default(), NetState::default());
+        for _ in 0..3 {
+            let expected = net
+                .predict_with_state(&[0.2, 0.0, -0.6], &mut state)
+                .unwrap();
+            let outputs = remapped
+                .predict_with_state(&[-0.6, 0.9, 0.2], &mut remapped_state)
+                .unwrap();
+            let (expected, outputs) = (expected.last().unwrap(), outputs.last().unwrap());
+            assert!((outputs[0] - expected[1]).abs() < 1e-12);
+            assert!((outputs[2] - expected[0]).abs() < 1e-12);
+        }
+        let new_output = remapped.predict(&[-0.6, 0.9, 0.2]).unwrap().last().unwrap()[1];
+        assert!((new_output - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn from_bytes_rejects_other_files() {
+        assert!(Net::from_bytes(b"not a brain").is_err());
+    }
+
+    #[test]
+    fn crossover_takes_weights_from_the_parents() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let a = Net::new(vec![3, 4, 2], &mut rng).unwrap();
+        let b = Net::new(vec![3, 4, 2], &mut rng).unwrap();
+        for mode in [
+            Crossover::Uniform,
+            Crossover::Neuron,
+            Crossover::SinglePoint,
+        ] {
+            let child = a.crossover(&b, mode, &mut rng);
+            for (i, layer) in child.layers.iter().enumerate() {
+                let weights = layer.nodes.iter().flatten();
+                let from_a = a.layers[i].nodes.iter().flatten();
+                let from_b = b.layers[i].nodes.iter().flatten();
+                for ((w, x), y) in weights.zip(from_a).zip(from_b) {
+                    assert!(w == x || w == y, "{:?}", mode);
+                }
+            }
+        }
+
+        let clone = a.crossover(&b, Crossover::None, &mut rng);
+        assert_eq!(clone.layers[1].nodes, a.layers[1].nodes);
+    }
+
+    #[test]
+    fn flat_net_matches_dense_predict() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let net = Net::with_activations(
+            vec![6, 5, 3],
+            vec![Activation::Tanh, Activation::Sigmoid],
+            &mut rng,
+        )
+        .unwrap();
+
+        let flat = FlatNet::new(&net);
+        let mut scratch = Vec::new();
+        let mut outputs = vec![0.0; 3];
+        for _ in 0..5 {
+            let inputs = random_inputs(6, &mut rng);
+            let expected = net.predict(&inputs).unwrap();
+            flat.predict_into(
+                &inputs,
+                &mut NetState::default(),
+                &mut scratch,
+                &mut outputs,
+            )
+            .unwrap();
+            assert_eq!(&outputs, expected.last().unwrap());
+        }
+    }
+
+    #[test]
+    fn flat_net_matches_elman_predict() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let net = Net::with_layer_kinds(
+            vec![4, 6, 2, 3],
+            vec![Activation::Relu, Activation::Tanh, Activation::Sigmoid],
+            vec![LayerKind::Elman, LayerKind::Dense, LayerKind::Elman],
+            &mut rng,
+        )
+        .unwrap();
 
-    fn sigmoid(&self, y: f64) -> f64 {
-        1f64 / (1f64 + (-y).exp())
+        assert_flat_matches(&net, &mut rng);
     }
 }
@@ -1,9 +1,10 @@
-use std::time::Instant;
-
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use crate::{bullet::Bullet, cell::energy::EnergyMap, food::Food, settings::DynamicSettings, *};
+use crate::{
+    bullet::Bullet, cell::energy::EnergyMap, food::Food, settings::DynamicSettings,
+    trackers::InstantTracker, *,
+};
 
 pub struct PhysicsPlugin;
 
@@ -18,6 +19,11 @@ impl Plugin for PhysicsPlugin {
 
 fn setup(mut rapier_config: ResMut<RapierConfiguration>) {
     rapier_config.gravity = Vec2::ZERO;
+    // Same step every frame, so the physics follows the sim clock and not the frame time
+    rapier_config.timestep_mode = TimestepMode::Fixed {
+        dt: SIM_TICK_SECS,
+        substeps: 1,
+    };
 }
 
 fn handle_collision_events(
@@ -38,12 +44,12 @@ fn handle_collision_events(
                         match energy_map.0.get_key_value_mut(&(b.0)) {
                             Some((_, (v, i))) => {
                                 *v = MAX_ENERGY.min(*v + settings.energy_per_food);
-                                *i = Instant::now();
+                                *i = InstantTracker::now();
                             }
                             None => {
                                 energy_map
                                     .0
-                                    .insert(b.0, (settings.energy_per_food, Instant::now()));
+                                    .insert(b.0, (settings.energy_per_food, InstantTracker::now()));
                             }
                         }
                     }
@@ -55,12 +61,12 @@ fn handle_collision_events(
                         match energy_map.0.get_key_value_mut(&(b.0)) {
                             Some((_, (v, i))) => {
                                 *v = MAX_ENERGY.min(*v + settings.energy_per_food);
-                                *i = Instant::now();
+                                *i = InstantTracker::now();
                             }
                             None => {
                                 energy_map
                                     .0
-                                    .insert(b.0, (settings.energy_per_food, Instant::now()));
+                                    .insert(b.0, (settings.energy_per_food, InstantTracker::now()));
                             }
                         }
                     }
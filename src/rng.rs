@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::settings::LaunchArgs;
+
+pub struct SimRngPlugin;
+
+/// Every random draw in the simulation comes from here, so a run's draws can be repeated from its seed
+/// Each subsystem has its own stream, so changes in one don't shift the numbers of another
+/// Timers count sim ticks, so with the same seed and settings a run plays out the same way
+#[derive(Resource)]
+pub struct SimRng {
+    seed: u64,
+    /// Seed picked in the settings panel for the next reset or launch, the running streams keep theirs
+    pub next_seed: u64,
+    pub brains: StdRng,
+    pub cells: StdRng,
+    pub food: StdRng,
+    pub replication: StdRng,
+}
+
+/// Starts the run over from `SimRng::next_seed`, each plugin clears its own state on it
+#[derive(Event)]
+pub struct ResetRunEvent;
+
+impl Plugin for SimRngPlugin {
+    fn build(&self, app: &mut App) {
+        let seed = app
+            .world
+            .get_resource::<LaunchArgs>()
+            .and_then(|args| args.seed)
+            // Small enough to be edited exactly in the settings panel
+            .unwrap_or_else(|| rand::thread_rng().gen::<u32>() as u64);
+        info!("Simulation seed: {}", seed);
+
+        app.insert_resource(SimRng::new(seed))
+            .add_event::<ResetRunEvent>()
+            .add_systems(PreUpdate, reseed.run_if(on_event::<ResetRunEvent>()));
+    }
+}
+
+fn reseed(mut sim_rng: ResMut<SimRng>) {
+    *sim_rng = SimRng::new(sim_rng.next_seed);
+    info!("Simulation seed: {}", sim_rng.seed());
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            next_seed: seed,
+            brains: Self::stream(seed, 0),
+            cells: Self::stream(seed, 1),
+            food: Self::stream(seed, 2),
+            replication: Self::stream(seed, 3),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn stream(seed: u64, index: u64) -> StdRng {
+        // Spread the streams apart, so seeds n and n + 1 don't share any of them
+        StdRng::seed_from_u64(seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+}
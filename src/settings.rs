@@ -1,6 +1,17 @@
+use std::{env, path::PathBuf};
+
 use bevy::prelude::*;
 
-use crate::*;
+use crate::{
+    cell::{
+        focus::ExportFocusedBrainEvent,
+        lineage::{ExportLineageEvent, LineageFormat},
+        trainer::TrainerMode,
+        ActionMode,
+    },
+    nn::{Crossover, NetFormat},
+    *,
+};
 
 pub struct SettingsPlugin;
 
@@ -13,12 +24,29 @@ pub struct SimSettings {
     pub follow_focused_cell: bool,
 }
 
+/// Options passed on the command line
+/// `--brains <path>` seeds the population from a brain file or a directory of them
+/// `--seed <n>` seeds the simulation's random numbers, to repeat a run's draws
+/// `--demo <path>` adds a brain cloned from a user cell recording to the seeds
+#[derive(Resource, Default)]
+pub struct LaunchArgs {
+    pub brains: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub demo: Option<PathBuf>,
+}
+
 #[derive(Resource)]
 pub struct DynamicSettings {
     pub bullet_miss_penalty: f32,
     pub energy_per_food: f32,
     pub energy_decay_rate: f32,
     pub num_food: usize,
+    pub crossover: Crossover,
+    /// Divide a cell's energy between its species when picking parents
+    pub fitness_sharing: bool,
+    pub trainer: TrainerMode,
+    /// How brain outputs drive the cells
+    pub action_mode: ActionMode,
 }
 
 impl Default for SimSettings {
@@ -36,12 +64,18 @@ impl Default for SimSettings {
 impl Plugin for SettingsPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(SimSettings::default())
+            .insert_resource(LaunchArgs::from_env())
             .insert_resource(DynamicSettings::new())
             .add_systems(Update, handle_keyboard_input);
     }
 }
 
-fn handle_keyboard_input(keyboard_input: Res<Input<KeyCode>>, mut settings: ResMut<SimSettings>) {
+fn handle_keyboard_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<SimSettings>,
+    mut export_writer: EventWriter<ExportFocusedBrainEvent>,
+    mut lineage_writer: EventWriter<ExportLineageEvent>,
+) {
     if keyboard_input.just_pressed(KeyCode::Tab) {
         settings.show_side_panel = !settings.show_side_panel;
     }
@@ -57,6 +91,32 @@ fn handle_keyboard_input(keyboard_input: Res<Input<KeyCode>>, mut settings: ResM
     if keyboard_input.just_pressed(KeyCode::C) {
         settings.follow_focused_cell = !settings.follow_focused_cell;
     }
+    if keyboard_input.just_pressed(KeyCode::N) {
+        export_writer.send(ExportFocusedBrainEvent(NetFormat::Json));
+    }
+    if keyboard_input.just_pressed(KeyCode::L) {
+        lineage_writer.send(ExportLineageEvent(LineageFormat::Dot));
+    }
+}
+
+impl LaunchArgs {
+    pub fn from_env() -> Self {
+        let mut launch_args = Self::default();
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--brains" => launch_args.brains = args.next().map(PathBuf::from),
+                "--demo" => launch_args.demo = args.next().map(PathBuf::from),
+                "--seed" => match args.next().map(|s| s.parse::<u64>()) {
+                    Some(Ok(seed)) => launch_args.seed = Some(seed),
+                    _ => warn!("--seed needs a positive integer"),
+                },
+                _ => warn!("Ignoring unknown argument {}", arg),
+            }
+        }
+
+        launch_args
+    }
 }
 
 impl DynamicSettings {
@@ -66,6 +126,10 @@ impl DynamicSettings {
             energy_per_food: ENERGY_PER_FOOD,
             num_food: NUM_FOOD,
             energy_decay_rate: ENERGY_DECAY_RATE,
+            crossover: BRAIN_CROSSOVER,
+            fitness_sharing: SPECIES_FITNESS_SHARING,
+            trainer: TRAINER_MODE,
+            action_mode: ACTION_MODE,
         }
     }
 }
@@ -1,10 +1,17 @@
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use bevy::{prelude::*, time::common_conditions::on_timer};
+use bevy::prelude::*;
+
+use crate::{rng::ResetRunEvent, SIM_TICK_SECS};
 
 pub struct TrackersPlugin;
 
-pub struct InstantTracker(pub Instant);
+/// Frames simulated since the run started, the clock every timer reads
+static SIM_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Tick of the sim clock a tracker was last set at
+#[derive(Clone, Copy)]
+pub struct InstantTracker(pub u64);
 
 #[derive(Default, Component)]
 pub struct LastUpdated(pub InstantTracker);
@@ -26,13 +33,46 @@ pub struct OneSecondTimer(pub InstantTracker);
 
 impl Plugin for TrackersPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(OneSecondTimer::default()).add_systems(
-            Update,
-            update_second_timer.run_if(on_timer(Duration::from_secs_f32(1.0))),
-        );
+        app.insert_resource(OneSecondTimer::default())
+            .add_systems(First, advance_sim_clock)
+            .add_systems(
+                PreUpdate,
+                reset_sim_clock.run_if(on_event::<ResetRunEvent>()),
+            )
+            .add_systems(Update, update_second_timer.run_if(on_sim_timer(1.0)));
     }
 }
 
+pub fn sim_ticks() -> u64 {
+    SIM_TICKS.load(Ordering::Relaxed)
+}
+
+pub fn sim_seconds() -> f32 {
+    ticks_to_secs(sim_ticks())
+}
+
+fn ticks_to_secs(ticks: u64) -> f32 {
+    ticks as f32 * SIM_TICK_SECS
+}
+
+/// Run condition firing every `secs` of sim time, the sim clock version of `on_timer`
+pub fn on_sim_timer(secs: f32) -> impl FnMut() -> bool + Clone {
+    let period = ((secs / SIM_TICK_SECS).round() as u64).max(1);
+    move || {
+        let ticks = sim_ticks();
+        ticks > 0 && ticks.is_multiple_of(period)
+    }
+}
+
+fn advance_sim_clock() {
+    SIM_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn reset_sim_clock(mut one_second_timer: ResMut<OneSecondTimer>) {
+    SIM_TICKS.store(0, Ordering::Relaxed);
+    *one_second_timer = OneSecondTimer::default();
+}
+
 fn update_second_timer(mut one_second_timer: ResMut<OneSecondTimer>) {
     one_second_timer.0.set_instant_now();
 }
@@ -56,29 +96,33 @@ impl FitnessScores {
 }
 
 impl InstantTracker {
+    pub fn now() -> Self {
+        Self(sim_ticks())
+    }
+
     pub fn elapsed(&self) -> f32 {
-        self.get_instant().elapsed().as_secs_f32()
+        ticks_to_secs(sim_ticks().saturating_sub(self.0))
     }
 
     pub fn elapsed_past(&self, interval: f32) -> bool {
-        self.get_instant().elapsed().as_secs_f32() >= interval
+        self.elapsed() >= interval
     }
 
     pub fn elapsed_within(&self, interval: f32) -> bool {
-        self.get_instant().elapsed().as_secs_f32() < interval
+        self.elapsed() < interval
     }
 
-    pub fn get_instant(&self) -> Instant {
+    pub fn get_instant(&self) -> u64 {
         self.0
     }
 
     pub fn set_instant_now(&mut self) {
-        self.0 = Instant::now();
+        self.0 = sim_ticks();
     }
 }
 
 impl Default for InstantTracker {
     fn default() -> Self {
-        Self(Instant::now())
+        Self::now()
     }
 }